@@ -0,0 +1,159 @@
+// Converts the PNG files in `assets/` into run-length encoded `Image` statics,
+// which are included by `src/assets.rs`.
+
+extern crate png;
+
+use png::HasParameters;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const ASSET_DIR: &str = "assets";
+
+struct Bitmap {
+    width: u32,
+    height: u32,
+    // ARGB8888
+    pixels: Vec<u32>,
+}
+
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let mut out = File::create(out_dir.join("assets.rs")).unwrap();
+
+    println!("cargo:rerun-if-changed={}", ASSET_DIR);
+
+    let mut paths: Vec<PathBuf> = match fs::read_dir(ASSET_DIR) {
+        Ok(entries) => entries
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "png"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+
+    for path in paths.iter() {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let bitmap = load_png(path);
+        let name = static_name(path);
+        write_image(&mut out, &name, &bitmap);
+    }
+}
+
+fn static_name(path: &Path) -> String {
+    path.file_stem()
+        .unwrap()
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+fn load_png(path: &Path) -> Bitmap {
+    let mut decoder = png::Decoder::new(File::open(path).unwrap());
+    // palettes and low bit depths are expanded to 8 bits per channel
+    decoder.set(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info().unwrap();
+    // the frame has the layout after the transformations, a transparent color
+    // for example adds an alpha channel
+    let (color_type, bit_depth) = reader.output_color_type();
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut buffer).unwrap();
+
+    if bit_depth != png::BitDepth::Eight {
+        panic!("{}: only 8 bit images are supported", path.display());
+    }
+
+    let channels = match color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => panic!("{}: palette was not expanded", path.display()),
+    };
+
+    let pixels = buffer
+        .chunks(channels)
+        .map(|p| {
+            let (r, g, b, a) = match channels {
+                1 => (p[0], p[0], p[0], 255),
+                2 => (p[0], p[0], p[0], p[1]),
+                3 => (p[0], p[1], p[2], 255),
+                _ => (p[0], p[1], p[2], p[3]),
+            };
+            // fully transparent pixels all get the same value, which gives longer runs
+            if a == 0 {
+                0
+            } else {
+                (a as u32) << 24 | (r as u32) << 16 | (g as u32) << 8 | b as u32
+            }
+        })
+        .collect();
+
+    Bitmap {
+        width: info.width,
+        height: info.height,
+        pixels: pixels,
+    }
+}
+
+/// Splits `values` into runs of equal values, each at most 255 long.
+fn runs<T: PartialEq + Copy>(values: &[T]) -> Vec<(u8, T)> {
+    let mut result: Vec<(u8, T)> = Vec::new();
+    for &value in values.iter() {
+        let extend = match result.last() {
+            Some(&(count, last)) => last == value && count < 255,
+            None => false,
+        };
+        if extend {
+            result.last_mut().unwrap().0 += 1;
+        } else {
+            result.push((1, value));
+        }
+    }
+    result
+}
+
+fn write_image(out: &mut File, name: &str, bitmap: &Bitmap) {
+    let mut palette: Vec<u32> = Vec::new();
+    for &pixel in bitmap.pixels.iter() {
+        if !palette.contains(&pixel) {
+            palette.push(pixel);
+        }
+    }
+
+    let mut data: Vec<u8> = Vec::new();
+    let format;
+    if palette.len() <= 256 {
+        format = "Indexed";
+        let indices: Vec<u8> = bitmap
+            .pixels
+            .iter()
+            .map(|pixel| palette.iter().position(|p| p == pixel).unwrap() as u8)
+            .collect();
+        for (count, index) in runs(&indices) {
+            data.push(count);
+            data.push(index);
+        }
+    } else {
+        format = "Rgba";
+        palette.clear();
+        for (count, pixel) in runs(&bitmap.pixels) {
+            data.push(count);
+            data.push((pixel >> 24) as u8);
+            data.push((pixel >> 16) as u8);
+            data.push((pixel >> 8) as u8);
+            data.push(pixel as u8);
+        }
+    }
+
+    writeln!(out, "static {}_PALETTE: [u32; {}] = {:?};", name, palette.len(), palette).unwrap();
+    writeln!(out, "static {}_DATA: [u8; {}] = {:?};", name, data.len(), data).unwrap();
+    writeln!(
+        out,
+        "pub static {}: Image = Image {{ width: {}, height: {}, format: ImageFormat::{}, \
+         compressed: true, palette: &{}_PALETTE, data: &{}_DATA }};",
+        name, bitmap.width, bitmap.height, format, name, name
+    ).unwrap();
+}
@@ -0,0 +1,50 @@
+// Builds the modules of the game that don't touch the hardware for the host,
+// so their tests run without the board. The game itself only builds for the
+// `stm32f7` target that `.cargo/config` selects, so the host target has to be
+// given explicitly, which `test.sh` and `test.bat` do:
+//
+//     cargo test --manifest-path host_tests/Cargo.toml --target <host triple>
+
+// the modules use `core` like the `no_std` game does
+extern crate core;
+
+#[path = "../../src/backend.rs"]
+mod backend;
+
+#[path = "../../src/composite.rs"]
+mod composite;
+
+#[path = "../../src/pixel_format.rs"]
+mod pixel_format;
+
+#[path = "../../src/raster.rs"]
+mod raster;
+
+// stands in for the board crate, only the colour type is used by the modules
+// above
+mod stm32f7 {
+    pub mod lcd {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct Color {
+            pub red: u8,
+            pub green: u8,
+            pub blue: u8,
+            pub alpha: u8,
+        }
+
+        impl Color {
+            pub fn rgb(red: u8, green: u8, blue: u8) -> Color {
+                Color::rgba(red, green, blue, 255)
+            }
+
+            pub fn rgba(red: u8, green: u8, blue: u8, alpha: u8) -> Color {
+                Color {
+                    red: red,
+                    green: green,
+                    blue: blue,
+                    alpha: alpha,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+#!/usr/bin/env python
+"""Pretty-prints the log of the firmware.
+
+The firmware writes lines of the form `<ticks> <LEVEL> <tag>: <message>`,
+either over semihosting (plain text on stderr of gdb) or to ITM stimulus
+port 0, which is captured from the SWO pin as a stream of ITM packets, e.g.
+with openocd:
+
+    tpiu config internal swo.bin uart off 216000000 2000000
+
+Usage:
+    python log_decoder.py swo.bin            decode a raw SWO capture
+    python log_decoder.py -f swo.bin         keep reading while the file grows
+    python log_decoder.py --text < log.txt   format semihosting output
+"""
+from __future__ import print_function
+import argparse
+import re
+import sys
+import time
+
+LOG_PORT = 0
+
+LINE = re.compile(r'^(\d+) (ERROR|WARN|INFO|DEBUG|TRACE) ([^:]*): (.*)$')
+
+COLORS = {
+    'ERROR': '\033[31m',
+    'WARN': '\033[33m',
+    'INFO': '\033[32m',
+    'DEBUG': '\033[36m',
+    'TRACE': '\033[90m',
+}
+RESET = '\033[0m'
+
+
+class ItmDecoder(object):
+    """Splits a byte stream into ITM packets and collects the payload of the
+    software source packets per stimulus port."""
+
+    def __init__(self):
+        self.buffer = bytearray()
+        self.ports = {}
+
+    def feed(self, data):
+        self.buffer.extend(data)
+        while self.buffer:
+            size = self.packet_size(self.buffer)
+            if size is None or size > len(self.buffer):
+                # incomplete packet, wait for more data
+                break
+            packet = self.buffer[:size]
+            del self.buffer[:size]
+            self.handle(packet)
+
+    @staticmethod
+    def packet_size(buffer):
+        header = buffer[0]
+        if header == 0x00:
+            # synchronization packet, a run of zeros ending with 0x80
+            for i, byte in enumerate(buffer):
+                if byte == 0x80:
+                    return i + 1
+                if byte != 0x00:
+                    return i
+            return None
+        if header == 0x70:
+            # overflow
+            return 1
+        if header & 0x03:
+            # source packet with 1, 2 or 4 payload bytes
+            return 1 + (1, 2, 4)[(header & 0x03) - 1]
+        # timestamps and extension packets continue while bit 7 is set
+        if not header & 0x80:
+            return 1
+        for i in range(1, len(buffer)):
+            if not buffer[i] & 0x80:
+                return i + 1
+        return None
+
+    def handle(self, packet):
+        header = packet[0]
+        # only software source packets, hardware packets set bit 2
+        if header & 0x03 and not header & 0x04:
+            port = header >> 3
+            self.ports.setdefault(port, bytearray()).extend(packet[1:])
+
+    def take(self, port):
+        data = self.ports.pop(port, bytearray())
+        return bytes(data)
+
+
+def format_line(line, color):
+    m = LINE.match(line)
+    if not m:
+        return line
+    ticks, level, tag, message = m.groups()
+    seconds = int(ticks) / 1000.0
+    text = '{:10.3f} {:<5} {:<12} {}'.format(seconds, level, tag, message)
+    if color:
+        text = COLORS[level] + text + RESET
+    return text
+
+
+class LinePrinter(object):
+    def __init__(self, color):
+        self.color = color
+        self.pending = ''
+
+    def feed(self, text):
+        lines = (self.pending + text).split('\n')
+        self.pending = lines.pop()
+        for line in lines:
+            print(format_line(line.rstrip('\r'), self.color))
+        sys.stdout.flush()
+
+
+def decode_swo(stream, printer, follow):
+    decoder = ItmDecoder()
+    while True:
+        data = stream.read(4096)
+        if not data:
+            if not follow:
+                break
+            time.sleep(0.1)
+            continue
+        decoder.feed(bytearray(data))
+        printer.feed(decoder.take(LOG_PORT).decode('utf8', 'replace'))
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.split('\n')[0])
+    parser.add_argument('file', nargs='?', help='capture file, stdin if missing')
+    parser.add_argument('-f', '--follow', action='store_true',
+                        help='wait for more data at the end of the file')
+    parser.add_argument('--text', action='store_true',
+                        help='the input is plain text from semihosting')
+    parser.add_argument('--no-color', action='store_true')
+    args = parser.parse_args()
+
+    color = not args.no_color and sys.stdout.isatty()
+    printer = LinePrinter(color)
+
+    if args.text:
+        stream = open(args.file) if args.file else sys.stdin
+        for line in stream:
+            printer.feed(line)
+    else:
+        stream = open(args.file, 'rb') if args.file else getattr(sys.stdin, 'buffer', sys.stdin)
+        decode_swo(stream, printer, args.follow)
+
+
+if __name__ == '__main__':
+    main()
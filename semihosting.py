@@ -3,11 +3,23 @@ import gdb
 import re
 import struct
 import sys
+import time
 
 
 class SemiHostHelper(object):
+    # the firmware writes to these without opening them
     SANE_FDS = (1, 2)
 
+    # fopen modes in the order of the semihosting specification
+    OPEN_MODES = ('r', 'rb', 'r+', 'r+b', 'w', 'wb', 'w+', 'w+b',
+                  'a', 'ab', 'a+', 'a+b')
+
+    ADP_STOPPED_APPLICATION_EXIT = 0x20026
+
+    files = {}
+    next_fd = 3
+    start_time = time.time()
+
     @classmethod
     def on_break(cls):
         # get the current frame and inferior
@@ -33,34 +45,81 @@ class SemiHostHelper(object):
                 r1 = gdb.parse_and_eval('$r1')
 
                 call_type = int(r0)
-                arg_addr = int(r1)
+                arg_addr = int(r1) & 0xffffffff
 
-                if call_type == 0x05:
-                    cls.handle_write(inf, arg_addr)
-                else:
+                handlers = {
+                    0x01: cls.handle_open,
+                    0x02: cls.handle_close,
+                    0x05: cls.handle_write,
+                    0x06: cls.handle_read,
+                    0x10: cls.handle_clock,
+                    0x11: cls.handle_time,
+                    0x18: cls.handle_exit,
+                    0x20: cls.handle_exit_extended,
+                }
+                if call_type not in handlers:
                     raise NotImplementedError(
                         'Call type 0x{:X} not implemented'
                         .format(call_type))
+                result = handlers[call_type](inf, arg_addr)
+                if result is not None:
+                    gdb.execute('set $r0 = {}'.format(result & 0xffffffff))
             else:
                 raise ValueError('no semi-hosting breakpoint')
         else:
             raise ValueError('no bkpt instruction')
 
+    @staticmethod
+    def read_args(inf, args_addr, count):
+        buf = inf.read_memory(args_addr, 4 * count)
+        return struct.unpack('<' + 'L' * count, buf)
+
+    @classmethod
+    def handle_open(cls, inf, args_addr):
+        # argument struct: name address, mode, name length
+        addr, mode, l = cls.read_args(inf, args_addr, 3)
+        name = bytes(inf.read_memory(addr, l)).decode('utf8')
+
+        # the special name :tt is the console
+        if name == ':tt':
+            return 2 if mode >= 8 else 1 if mode >= 4 else 0
+
+        if mode >= len(cls.OPEN_MODES):
+            return -1
+        try:
+            f = open(name, cls.OPEN_MODES[mode])
+        except IOError as e:
+            print('semihosting: cannot open {}: {}'.format(name, e),
+                  file=sys.stderr)
+            return -1
+
+        fd = cls.next_fd
+        cls.next_fd += 1
+        cls.files[fd] = f
+        return fd
+
+    @classmethod
+    def handle_close(cls, inf, args_addr):
+        fd, = cls.read_args(inf, args_addr, 1)
+        f = cls.files.pop(fd, None)
+        if f is None:
+            return 0 if fd in cls.SANE_FDS else -1
+        f.close()
+        return 0
+
     @classmethod
     def handle_write(cls, inf, args_addr):
         # argument struct has three u32 entries: fd, address, len
-        buf = inf.read_memory(args_addr, 12)
-
-        fd, addr, l = struct.unpack('<lll', buf)
+        fd, addr, l = cls.read_args(inf, args_addr, 3)
 
         # limit length to 4M to avoid funky behavior
         l = min(l, 4 * 1024 * 1024)
 
         # sanity check file descriptor
-        if fd not in cls.SANE_FDS:
+        if fd not in cls.SANE_FDS and fd not in cls.files:
             raise ValueError(
                 'Refusing to write to file descriptor {}'
-                ' (not in {})'.format(fd, cls.SANE_FDS))
+                ' (neither in {} nor opened)'.format(fd, cls.SANE_FDS))
 
         # read the memory
         data = bytes(inf.read_memory(addr, l))
@@ -70,3 +129,61 @@ class SemiHostHelper(object):
             sys.stdout.write(data.decode('utf8'))
         elif fd == 2:
             sys.stderr.write(data.decode('utf8'))
+        else:
+            f = cls.files[fd]
+            f.write(data if 'b' in f.mode else data.decode('utf8'))
+
+        # the number of bytes that were not written
+        return 0
+
+    @classmethod
+    def handle_read(cls, inf, args_addr):
+        fd, addr, l = cls.read_args(inf, args_addr, 3)
+        l = min(l, 4 * 1024 * 1024)
+
+        if fd == 0:
+            data = sys.stdin.readline(l).encode('utf8')
+        elif fd in cls.files:
+            data = cls.files[fd].read(l)
+            if not isinstance(data, bytes):
+                data = data.encode('utf8')
+        else:
+            return l
+
+        if data:
+            inf.write_memory(addr, data)
+        # the number of bytes that were not read
+        return l - len(data)
+
+    @classmethod
+    def handle_clock(cls, inf, args_addr):
+        # centiseconds since the start of the session
+        return int((time.time() - cls.start_time) * 100)
+
+    @classmethod
+    def handle_time(cls, inf, args_addr):
+        return int(time.time())
+
+    @classmethod
+    def exit(cls, code):
+        for f in cls.files.values():
+            f.close()
+        cls.files.clear()
+        sys.stdout.flush()
+        print('semihosting: application exited with {}'.format(code),
+              file=sys.stderr)
+        gdb.execute('kill')
+        gdb.execute('quit {}'.format(code))
+
+    @classmethod
+    def handle_exit(cls, inf, args_addr):
+        # on 32 bit targets the reason is passed directly in r1
+        reason = args_addr
+        cls.exit(0 if reason == cls.ADP_STOPPED_APPLICATION_EXIT else 1)
+
+    @classmethod
+    def handle_exit_extended(cls, inf, args_addr):
+        reason, code = cls.read_args(inf, args_addr, 2)
+        if reason != cls.ADP_STOPPED_APPLICATION_EXIT:
+            code = 1
+        cls.exit(code)
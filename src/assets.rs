@@ -0,0 +1,6 @@
+#![allow(dead_code)]
+
+use image::{Image, ImageFormat};
+
+// generated by build.rs from the PNG files in assets/
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
@@ -0,0 +1,251 @@
+#![allow(dead_code)]
+
+use core::ptr;
+
+/// A 32 bit ARGB8888 pixel buffer in memory, e.g. a LCD layer or the background cache.
+#[derive(Clone, Copy)]
+pub struct Surface {
+    pub address: usize,
+    /// Number of pixels per line
+    pub line_width: i32,
+}
+
+impl Surface {
+    pub fn new(address: usize, line_width: i32) -> Surface {
+        Surface {
+            address: address,
+            line_width: line_width,
+        }
+    }
+
+    fn pixel_ptr(&self, x: i32, y: i32) -> *mut u32 {
+        (self.address as *mut u32).wrapping_offset((x + y * self.line_width) as isize)
+    }
+}
+
+/// Block operations on surfaces. Rectangles have to be clipped by the caller.
+pub trait Backend {
+    fn fill(&mut self, target: &Surface, x: i32, y: i32, w: i32, h: i32, value: u32);
+
+    /// Fills several rectangles given as (x, y, width, height) with the same value.
+    fn fill_rects(&mut self, target: &Surface, rects: &[(i32, i32, i32, i32)], value: u32) {
+        for &(x, y, w, h) in rects.iter() {
+            self.fill(target, x, y, w, h, value);
+        }
+    }
+
+    /// Copies a rectangle from `source` to the same position in `target`.
+    fn copy(&mut self, source: &Surface, target: &Surface, x: i32, y: i32, w: i32, h: i32);
+
+    /// Blends a rectangle of `source` at (`sx`, `sy`) over `target` at (`x`, `y`)
+    /// using the source alpha.
+    fn blend(&mut self, source: &Surface, sx: i32, sy: i32, target: &Surface, x: i32, y: i32, w: i32, h: i32);
+}
+
+pub fn blend_pixel(source: u32, target: u32) -> u32 {
+    let alpha = source >> 24;
+    if alpha == 255 {
+        return source;
+    }
+    if alpha == 0 {
+        return target;
+    }
+    let mut result = 0xff00_0000;
+    for &shift in [0u32, 8, 16].iter() {
+        let s = (source >> shift) & 0xff;
+        let t = (target >> shift) & 0xff;
+        let c = (s * alpha + t * (255 - alpha) + 127) / 255;
+        result |= c << shift;
+    }
+    result
+}
+
+/// Reference implementation on the CPU.
+pub struct SoftwareBackend;
+
+impl Backend for SoftwareBackend {
+    fn fill(&mut self, target: &Surface, x: i32, y: i32, w: i32, h: i32, value: u32) {
+        for py in y..y + h {
+            let row = target.pixel_ptr(x, py);
+            for i in 0..w {
+                unsafe { ptr::write_volatile(row.offset(i as isize), value) };
+            }
+        }
+    }
+
+    fn copy(&mut self, source: &Surface, target: &Surface, x: i32, y: i32, w: i32, h: i32) {
+        for py in y..y + h {
+            unsafe {
+                ptr::copy_nonoverlapping(source.pixel_ptr(x, py), target.pixel_ptr(x, py), w as usize);
+            }
+        }
+    }
+
+    fn blend(&mut self, source: &Surface, sx: i32, sy: i32, target: &Surface, x: i32, y: i32, w: i32, h: i32) {
+        for j in 0..h {
+            let source_row = source.pixel_ptr(sx, sy + j);
+            let target_row = target.pixel_ptr(x, y + j);
+            for i in 0..w {
+                unsafe {
+                    let s = ptr::read_volatile(source_row.offset(i as isize));
+                    let t = ptr::read_volatile(target_row.offset(i as isize));
+                    ptr::write_volatile(target_row.offset(i as isize), blend_pixel(s, t));
+                }
+            }
+        }
+    }
+}
+
+/// Where a DMA2D transfer reads or writes a rectangle of a surface, in the
+/// form the address, offset and number of lines registers take it. Kept apart
+/// from the register access in `dma2d_backend`, so the addressing can be
+/// checked on the host.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TransferLayout {
+    pub address: usize,
+    /// Pixels skipped at the end of every line
+    pub line_offset: u16,
+    pub pixels_per_line: u16,
+    pub lines: u16,
+}
+
+impl TransferLayout {
+    pub fn new(surface: &Surface, x: i32, y: i32, w: i32, h: i32) -> TransferLayout {
+        TransferLayout {
+            address: surface.pixel_ptr(x, y) as usize,
+            line_offset: (surface.line_width - w) as u16,
+            pixels_per_line: w as u16,
+            lines: h as u16,
+        }
+    }
+}
+
+// smaller rectangles are written by the CPU while the DMA2D is busy with the
+// previous one, setting up a transfer takes longer than writing them
+const MIN_DMA_PIXELS: i32 = 32;
+
+pub fn uses_dma(w: i32, h: i32) -> bool {
+    w * h >= MIN_DMA_PIXELS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: i32 = 40;
+    const HEIGHT: i32 = 30;
+
+    // the DMA2D transfer modes (CR.MODE) that `Dma2dBackend` uses
+    const MODE_FILL: u8 = 0b11;
+    const MODE_COPY: u8 = 0b00;
+    const MODE_BLEND: u8 = 0b10;
+
+    fn buffer(seed: u32) -> Vec<u32> {
+        (0..(WIDTH * HEIGHT) as u32)
+            .map(|i| (i ^ seed).wrapping_mul(0x9E37_79B9))
+            .collect()
+    }
+
+    fn surface(buffer: &mut Vec<u32>) -> Surface {
+        Surface::new(buffer.as_mut_ptr() as usize, WIDTH)
+    }
+
+    /// Walks the memory like the DMA2D does with the given register values.
+    fn run_transfer(mode: u8, output: TransferLayout, foreground: TransferLayout, background: TransferLayout, color: u32) {
+        for line in 0..output.lines as usize {
+            for i in 0..output.pixels_per_line as usize {
+                let pixel = |layout: &TransferLayout| {
+                    let stride = layout.pixels_per_line as usize + layout.line_offset as usize;
+                    (layout.address as *mut u32).wrapping_offset((line * stride + i) as isize)
+                };
+                unsafe {
+                    let value = match mode {
+                        MODE_FILL => color,
+                        MODE_COPY => *pixel(&foreground),
+                        _ => blend_pixel(*pixel(&foreground), *pixel(&background)),
+                    };
+                    *pixel(&output) = value;
+                }
+            }
+        }
+    }
+
+    /// Does what `Dma2dBackend::fill_rects` does, with `run_transfer` for the DMA2D.
+    fn dma2d_fill_rects(target: &Surface, rects: &[(i32, i32, i32, i32)], value: u32) {
+        for &(x, y, w, h) in rects.iter() {
+            if !uses_dma(w, h) {
+                SoftwareBackend.fill(target, x, y, w, h, value);
+                continue;
+            }
+            let layout = TransferLayout::new(target, x, y, w, h);
+            run_transfer(MODE_FILL, layout, layout, layout, value);
+        }
+    }
+
+    static RECTS: [(i32, i32, i32, i32); 6] = [
+        (0, 0, WIDTH, HEIGHT),
+        (3, 4, 10, 7),
+        (WIDTH - 1, 0, 1, HEIGHT),
+        (5, HEIGHT - 1, 20, 1),
+        (12, 9, 3, 2),
+        (20, 10, 17, 15),
+    ];
+
+    #[test]
+    fn fill_matches_software() {
+        for &(x, y, w, h) in RECTS.iter() {
+            let mut expected = buffer(1);
+            let mut actual = buffer(1);
+            SoftwareBackend.fill(&surface(&mut expected), x, y, w, h, 0x80FF_2040);
+            let layout = TransferLayout::new(&surface(&mut actual), x, y, w, h);
+            run_transfer(MODE_FILL, layout, layout, layout, 0x80FF_2040);
+            assert!(expected == actual, "fill of {:?} differs", (x, y, w, h));
+        }
+    }
+
+    #[test]
+    fn batched_fill_matches_software() {
+        let mut expected = buffer(2);
+        let mut actual = buffer(2);
+        SoftwareBackend.fill_rects(&surface(&mut expected), &RECTS[1..], 0xFF12_3456);
+        dma2d_fill_rects(&surface(&mut actual), &RECTS[1..], 0xFF12_3456);
+        assert!(expected == actual);
+    }
+
+    #[test]
+    fn copy_matches_software() {
+        for &(x, y, w, h) in RECTS.iter() {
+            let mut source = buffer(3);
+            let mut expected = buffer(4);
+            let mut actual = buffer(4);
+            SoftwareBackend.copy(&surface(&mut source), &surface(&mut expected), x, y, w, h);
+            let foreground = TransferLayout::new(&surface(&mut source), x, y, w, h);
+            let output = TransferLayout::new(&surface(&mut actual), x, y, w, h);
+            run_transfer(MODE_COPY, output, foreground, output, 0);
+            assert!(expected == actual, "copy of {:?} differs", (x, y, w, h));
+        }
+    }
+
+    #[test]
+    fn blend_matches_software() {
+        for &(x, y, w, h) in RECTS.iter().skip(1) {
+            // the source rectangle lies somewhere else than the target
+            let (sx, sy) = (WIDTH - x - w, HEIGHT - y - h);
+            let mut source = buffer(5);
+            let mut expected = buffer(6);
+            let mut actual = buffer(6);
+            SoftwareBackend.blend(&surface(&mut source), sx, sy, &surface(&mut expected), x, y, w, h);
+            let foreground = TransferLayout::new(&surface(&mut source), sx, sy, w, h);
+            let output = TransferLayout::new(&surface(&mut actual), x, y, w, h);
+            run_transfer(MODE_BLEND, output, foreground, output, 0);
+            assert!(expected == actual, "blend of {:?} differs", (x, y, w, h));
+        }
+    }
+
+    #[test]
+    fn blend_pixel_keeps_opaque_and_transparent_pixels() {
+        assert_eq!(blend_pixel(0xFF11_2233, 0xFF44_5566), 0xFF11_2233);
+        assert_eq!(blend_pixel(0x0011_2233, 0xFF44_5566), 0xFF44_5566);
+        assert_eq!(blend_pixel(0x80FF_FFFF, 0xFF00_0000), 0xFF80_8080);
+    }
+}
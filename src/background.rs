@@ -0,0 +1,324 @@
+#![allow(dead_code)]
+
+extern crate alloc;
+extern crate stm32f7_discovery as stm32f7;
+
+use alloc::boxed::Box;
+use stm32f7::lcd::Color;
+
+use renderer::{mix_color, weight_color};
+
+pub const BACKGROUND_KIND_COUNT: i32 = 5;
+
+/// A procedural background that is evaluated per pixel in screen coordinates.
+///
+/// `scroll` is the distance the camera moved up since the start of the round,
+/// implementations move their content by a fraction of it to get a parallax effect.
+pub trait Background {
+    fn color(&mut self, x: i32, y: i32) -> Color;
+
+    fn set_scroll(&mut self, _scroll: i32) {}
+}
+
+impl<F: FnMut(i32, i32) -> Color> Background for F {
+    fn color(&mut self, x: i32, y: i32) -> Color {
+        self(x, y)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BackgroundKind {
+    Gradient,
+    Starfield,
+    Sunset,
+    Skyline,
+    Grid,
+    Clouds,
+    /// Cycles through the other kinds round by round
+    PerRound,
+}
+
+impl BackgroundKind {
+    pub fn for_round(&self, round: i32) -> BackgroundKind {
+        if *self != BackgroundKind::PerRound {
+            return *self;
+        }
+        match modulo(round, BACKGROUND_KIND_COUNT) {
+            0 => BackgroundKind::Starfield,
+            1 => BackgroundKind::Sunset,
+            2 => BackgroundKind::Skyline,
+            3 => BackgroundKind::Grid,
+            _ => BackgroundKind::Clouds,
+        }
+    }
+}
+
+pub fn create(
+    kind: BackgroundKind,
+    width: i32,
+    height: i32,
+    base_color: Color,
+    accent_color: Color,
+) -> Box<Background> {
+    match kind {
+        BackgroundKind::Gradient => Box::new(Starfield::new(width, height, base_color, accent_color, false)),
+        BackgroundKind::Starfield | BackgroundKind::PerRound => {
+            Box::new(Starfield::new(width, height, base_color, accent_color, true))
+        }
+        BackgroundKind::Sunset => Box::new(Sunset::new(width, height, base_color, accent_color)),
+        BackgroundKind::Skyline => Box::new(Skyline::new(height, base_color, accent_color)),
+        BackgroundKind::Grid => Box::new(Grid::new(height, base_color, accent_color)),
+        BackgroundKind::Clouds => Box::new(Clouds::new(height, base_color, accent_color)),
+    }
+}
+
+/// Vertical gradient from `base_color` at the top to `accent_color` at the bottom.
+fn gradient(base_color: Color, accent_color: Color, y: i32, height: i32) -> Color {
+    let alpha = y as f32 / height as f32;
+    let base = weight_color(base_color, 1f32 - alpha);
+    let mut color = weight_color(accent_color, alpha);
+    color.red = color.red.saturating_add(base.red);
+    color.green = color.green.saturating_add(base.green);
+    color.blue = color.blue.saturating_add(base.blue);
+    color
+}
+
+fn hash(x: i32, y: i32) -> i32 {
+    let mut h = x.wrapping_mul(374761393).wrapping_add(y.wrapping_mul(668265263));
+    h = (h ^ (h >> 13)).wrapping_mul(1274126177);
+    (h ^ (h >> 16)) & 0x7fffffff
+}
+
+fn modulo(value: i32, m: i32) -> i32 {
+    ((value % m) + m) % m
+}
+
+pub struct Starfield {
+    width: i32,
+    height: i32,
+    base_color: Color,
+    accent_color: Color,
+    stars: bool,
+    scroll: i32,
+}
+
+impl Starfield {
+    pub fn new(width: i32, height: i32, base_color: Color, accent_color: Color, stars: bool) -> Starfield {
+        Starfield {
+            width: width,
+            height: height,
+            base_color: base_color,
+            accent_color: accent_color,
+            stars: stars,
+            scroll: 0,
+        }
+    }
+}
+
+impl Background for Starfield {
+    fn color(&mut self, x: i32, y: i32) -> Color {
+        let ymax = self.height;
+        let mut color = gradient(self.base_color, self.accent_color, y, ymax);
+        let sy = y - self.scroll / 4;
+        // denser stars to the right, the divisor stays positive on any screen
+        let spacing = ymax + self.width + 200 - x;
+        if self.stars && 1329i32.wrapping_mul(x ^ sy.wrapping_mul(717)).wrapping_add(971) % spacing == 0 {
+            let mut alpha = y as f32 / ymax as f32;
+            alpha *= alpha;
+            alpha = 1f32 - alpha;
+            color = mix_color(color, Color::rgb(255, 255, 255), alpha);
+        }
+        color
+    }
+
+    fn set_scroll(&mut self, scroll: i32) {
+        self.scroll = scroll;
+    }
+}
+
+pub struct Sunset {
+    width: i32,
+    height: i32,
+    sky_color: Color,
+    horizon_color: Color,
+    sun_color: Color,
+    sun_radius: i32,
+    scroll: i32,
+}
+
+impl Sunset {
+    pub fn new(width: i32, height: i32, base_color: Color, accent_color: Color) -> Sunset {
+        Sunset {
+            width: width,
+            height: height,
+            sky_color: mix_color(base_color, accent_color, 0.5f32),
+            horizon_color: Color::rgb(255, 128, 64),
+            sun_color: Color::rgb(255, 224, 128),
+            sun_radius: width / 6,
+            scroll: 0,
+        }
+    }
+}
+
+impl Background for Sunset {
+    fn color(&mut self, x: i32, y: i32) -> Color {
+        // the sun sinks behind the horizon while the tower grows
+        let sun_y = self.height * 3 / 4 + self.scroll / 8;
+        let dx = x - self.width / 2;
+        let dy = y - sun_y;
+        if dx * dx + dy * dy < self.sun_radius * self.sun_radius {
+            return self.sun_color;
+        }
+        let t = y as f32 / self.height as f32;
+        mix_color(self.sky_color, self.horizon_color, t * t)
+    }
+
+    fn set_scroll(&mut self, scroll: i32) {
+        self.scroll = scroll;
+    }
+}
+
+pub struct Skyline {
+    height: i32,
+    base_color: Color,
+    accent_color: Color,
+    building_color: Color,
+    window_color: Color,
+    scroll: i32,
+}
+
+impl Skyline {
+    pub fn new(height: i32, base_color: Color, accent_color: Color) -> Skyline {
+        Skyline {
+            height: height,
+            base_color: base_color,
+            accent_color: accent_color,
+            building_color: weight_color(base_color, 0.3f32),
+            window_color: Color::rgb(160, 144, 64),
+            scroll: 0,
+        }
+    }
+}
+
+impl Background for Skyline {
+    fn color(&mut self, x: i32, y: i32) -> Color {
+        let building_width = 24;
+        let building = x / building_width;
+        let building_height = 30 + hash(building, 0) % 90;
+        let top = self.height - building_height + self.scroll / 2;
+        if y < top {
+            return gradient(self.base_color, self.accent_color, y, self.height);
+        }
+
+        let wx = modulo(x, building_width);
+        let wy = modulo(y - top, 8);
+        if wx > 2 && wx < building_width - 2 && wx % 5 < 3 && wy > 2 && wy < 6
+            && hash(x / 5, (y - top) / 8) % 4 == 0
+        {
+            self.window_color
+        } else {
+            self.building_color
+        }
+    }
+
+    fn set_scroll(&mut self, scroll: i32) {
+        self.scroll = scroll;
+    }
+}
+
+pub struct Grid {
+    height: i32,
+    spacing: i32,
+    base_color: Color,
+    accent_color: Color,
+    line_color: Color,
+    scroll: i32,
+}
+
+impl Grid {
+    pub fn new(height: i32, base_color: Color, accent_color: Color) -> Grid {
+        Grid {
+            height: height,
+            spacing: 24,
+            base_color: base_color,
+            accent_color: accent_color,
+            line_color: mix_color(accent_color, Color::rgb(255, 255, 255), 0.3f32),
+            scroll: 0,
+        }
+    }
+}
+
+impl Background for Grid {
+    fn color(&mut self, x: i32, y: i32) -> Color {
+        let color = gradient(self.base_color, self.accent_color, y, self.height);
+        if modulo(x, self.spacing) == 0 || modulo(y - self.scroll / 2, self.spacing) == 0 {
+            mix_color(color, self.line_color, y as f32 / self.height as f32)
+        } else {
+            color
+        }
+    }
+
+    fn set_scroll(&mut self, scroll: i32) {
+        self.scroll = scroll;
+    }
+}
+
+pub struct Clouds {
+    height: i32,
+    sky_color: Color,
+    horizon_color: Color,
+    cloud_color: Color,
+    scroll: i32,
+}
+
+impl Clouds {
+    pub fn new(height: i32, base_color: Color, accent_color: Color) -> Clouds {
+        Clouds {
+            height: height,
+            sky_color: mix_color(base_color, accent_color, 0.3f32),
+            horizon_color: mix_color(accent_color, Color::rgb(255, 255, 255), 0.5f32),
+            cloud_color: Color::rgb(255, 255, 255),
+            scroll: 0,
+        }
+    }
+
+    fn cloud_coverage(&self, x: i32, y: i32, layer: i32) -> bool {
+        let cell_width = 96 - 32 * layer;
+        let cell_height = 64 - 16 * layer;
+        // near clouds move faster than far clouds
+        let cy = y - self.scroll * (layer + 1) / 4;
+        let cx = x + 37 * layer;
+        let cell_x = cx / cell_width;
+        let cell_y = if cy < 0 { (cy + 1) / cell_height - 1 } else { cy / cell_height };
+        let h = hash(cell_x + 1000 * layer, cell_y);
+        if h % 3 != 0 {
+            return false;
+        }
+
+        let rx = cell_width / 3;
+        let ry = cell_height / 6;
+        let center_x = cell_x * cell_width + rx + h % (cell_width - 2 * rx + 1);
+        let center_y = cell_y * cell_height + ry + (h >> 8) % (cell_height - 2 * ry + 1);
+        let dx = (cx - center_x) as f32 / rx as f32;
+        let dy = (cy - center_y) as f32 / ry as f32;
+        dx * dx + dy * dy < 1f32
+    }
+}
+
+impl Background for Clouds {
+    fn color(&mut self, x: i32, y: i32) -> Color {
+        let t = y as f32 / self.height as f32;
+        let mut color = mix_color(self.sky_color, self.horizon_color, t);
+        if self.cloud_coverage(x, y, 0) {
+            color = mix_color(color, self.cloud_color, 0.5f32);
+        }
+        if self.cloud_coverage(x, y, 1) {
+            color = mix_color(color, self.cloud_color, 0.85f32);
+        }
+        color
+    }
+
+    fn set_scroll(&mut self, scroll: i32) {
+        self.scroll = scroll;
+    }
+}
@@ -4,6 +4,7 @@ extern crate stm32f7_discovery as stm32f7;
 
 use stm32f7::lcd::{Color, Framebuffer};
 
+use lighting::FaceColors;
 use renderer::Renderer;
 
 pub struct Block {
@@ -68,4 +69,19 @@ impl Block {
             top_color,
         );
     }
+
+    pub fn draw_shaded<T: Framebuffer>(&self, renderer: &mut Renderer<T>, base_x: i32, base_y: i32, faces: &FaceColors) {
+        renderer.draw_block_3d_shaded(
+            base_x + self.x + self.z,
+            base_y + self.y + self.x / 2 - self.z / 2,
+            self.width,
+            self.height,
+            self.depth,
+            faces.left_top,
+            faces.left_bottom,
+            faces.right_top,
+            faces.right_bottom,
+            faces.top,
+        );
+    }
 }
@@ -0,0 +1,103 @@
+#![allow(dead_code)]
+
+// Blending of the LTDC layers on plain ARGB8888 values. This module and
+// `pixel_format` do not depend on the hardware, so a host simulator can include
+// them with `#[path]` and produce the same images as the screenshots.
+
+use pixel_format::{expand_bits, PixelFormat};
+
+/// Pixel formats of a layer, numbered like the PF field of the LTDC LxPFCR register.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LayerFormat {
+    Argb8888,
+    Rgb888,
+    Rgb565,
+    Argb1555,
+    Argb4444,
+    L8,
+    Al44,
+    Al88,
+}
+
+impl LayerFormat {
+    pub fn from_register(value: u32) -> LayerFormat {
+        match value & 0b111 {
+            0 => LayerFormat::Argb8888,
+            1 => LayerFormat::Rgb888,
+            2 => LayerFormat::Rgb565,
+            3 => LayerFormat::Argb1555,
+            4 => LayerFormat::Argb4444,
+            5 => LayerFormat::L8,
+            6 => LayerFormat::Al44,
+            _ => LayerFormat::Al88,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        match *self {
+            LayerFormat::Argb8888 => 4,
+            LayerFormat::Rgb888 => 3,
+            LayerFormat::Rgb565 | LayerFormat::Argb1555 | LayerFormat::Argb4444 | LayerFormat::Al88 => 2,
+            LayerFormat::L8 | LayerFormat::Al44 => 1,
+        }
+    }
+
+    /// Converts a pixel value to ARGB8888. The formats the game draws in are
+    /// decoded by `PixelFormat`. The lookup table can't be read back from the
+    /// LTDC, so the luminance formats are treated as gray values.
+    pub fn decode(&self, value: u32) -> u32 {
+        match *self {
+            LayerFormat::Argb8888 => convert(PixelFormat::Argb8888, value),
+            LayerFormat::Rgb888 => 0xFF00_0000 | value & 0x00FF_FFFF,
+            LayerFormat::Rgb565 => convert(PixelFormat::Rgb565, value),
+            LayerFormat::Argb1555 => argb(
+                if value & 0x8000 != 0 { 0xFF } else { 0 },
+                expand(value >> 10, 5),
+                expand(value >> 5, 5),
+                expand(value, 5),
+            ),
+            LayerFormat::Argb4444 => convert(PixelFormat::Argb4444, value),
+            LayerFormat::L8 => gray(0xFF, value & 0xFF),
+            LayerFormat::Al44 => gray(expand(value >> 4, 4), expand(value, 4)),
+            LayerFormat::Al88 => gray((value >> 8) & 0xFF, value & 0xFF),
+        }
+    }
+}
+
+fn convert(format: PixelFormat, value: u32) -> u32 {
+    PixelFormat::Argb8888.encode(format.decode(value))
+}
+
+pub fn argb(a: u32, r: u32, g: u32, b: u32) -> u32 {
+    a << 24 | r << 16 | g << 8 | b
+}
+
+fn gray(alpha: u32, luminance: u32) -> u32 {
+    argb(alpha, luminance, luminance, luminance)
+}
+
+fn expand(value: u32, bits: u32) -> u32 {
+    expand_bits(value, bits) as u32
+}
+
+/// Blends `over` onto the opaque color `under` like the LTDC with its default
+/// blending factors: the pixel alpha multiplied by the constant alpha of the layer.
+pub fn blend(over: u32, under: u32, constant_alpha: u32) -> u32 {
+    let alpha = (over >> 24) * constant_alpha / 255;
+    let channel = |shift: u32| {
+        let o = (over >> shift) & 0xFF;
+        let u = (under >> shift) & 0xFF;
+        (o * alpha + u * (255 - alpha)) / 255
+    };
+    argb(0xFF, channel(16), channel(8), channel(0))
+}
+
+/// Composites the pixel of all layers, the lowest layer first, over the
+/// background color of the LTDC.
+pub fn composite(background: u32, layers: &[(u32, u32)]) -> u32 {
+    let mut result = 0xFF00_0000 | background;
+    for &(pixel, constant_alpha) in layers.iter() {
+        result = blend(pixel, result, constant_alpha);
+    }
+    result
+}
@@ -0,0 +1,250 @@
+#![allow(dead_code)]
+
+extern crate stm32f7_discovery as stm32f7;
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::Vec;
+use core::fmt::{self, Write};
+use core::ptr;
+use core::str;
+use core::sync::atomic::{AtomicBool, Ordering};
+use stm32f7::lcd::{Color, Framebuffer};
+use stm32f7::lcd::font::FontRenderer;
+
+use renderer::Renderer;
+use semihosting;
+
+const FONT_SIZE: f32 = 16.0;
+const LINE_HEIGHT: i32 = 16;
+const CHAR_WIDTH: i32 = 8;
+const MARGIN: i32 = 8;
+// words of the stack shown below the registers
+pub const STACK_WORDS: usize = 8;
+// longer panic messages and locations are cut off in the panic record
+const MESSAGE_BYTES: usize = 160;
+const LOCATION_BYTES: usize = 96;
+
+// marks a valid record in the uninitialized RAM
+const MAGIC: u32 = 0x9A41_C0DE;
+
+struct CrashScreen {
+    renderer: *mut (),
+    font_data: &'static [u8],
+    draw: fn(*mut (), &'static [u8], &str, &[String]),
+}
+
+static mut CRASH_SCREEN: Option<CrashScreen> = None;
+static CRASHED: AtomicBool = AtomicBool::new(false);
+
+/// A panic, kept over a reset so it can still be shown after the watchdog
+/// restarted the board.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PanicRecord {
+    magic: u32,
+    message: [u8; MESSAGE_BYTES],
+    message_len: usize,
+    location: [u8; LOCATION_BYTES],
+    location_len: usize,
+    sp: u32,
+    lr: u32,
+    stack: [u32; STACK_WORDS],
+}
+
+// not touched by the startup code, so the record survives a reset
+#[link_section = ".noinit"]
+static mut LAST_PANIC: PanicRecord = PanicRecord {
+    magic: 0,
+    message: [0; MESSAGE_BYTES],
+    message_len: 0,
+    location: [0; LOCATION_BYTES],
+    location_len: 0,
+    sp: 0,
+    lr: 0,
+    stack: [0; STACK_WORDS],
+};
+
+/// Lets the crash screen draw with `renderer`, which has to live until the
+/// end of the program, e.g. the renderer of the top layer created in `main`.
+pub fn register<T: Framebuffer>(renderer: &mut Renderer<T>, font_data: &'static [u8]) {
+    unsafe {
+        CRASH_SCREEN = Some(CrashScreen {
+            renderer: renderer as *mut Renderer<T> as *mut (),
+            font_data: font_data,
+            draw: draw_with::<T>,
+        });
+    }
+}
+
+fn draw_with<T: Framebuffer>(renderer: *mut (), font_data: &'static [u8], title: &str, lines: &[String]) {
+    // the program does not return to the owner of the renderer anymore
+    let renderer = unsafe { &mut *(renderer as *mut Renderer<T>) };
+    draw_screen(renderer, font_data, title, lines);
+}
+
+/// Fills the screen and shows the title and the lines, long lines are wrapped.
+pub fn draw_screen<T: Framebuffer>(renderer: &mut Renderer<T>, font_data: &'static [u8], title: &str, lines: &[String]) {
+    let font = FontRenderer::new(font_data, FONT_SIZE);
+    let width = renderer.get_width();
+    let height = renderer.get_height();
+    let color = Color::rgb(255, 255, 255);
+
+    renderer.set_direct();
+    renderer.draw_rect_solid(0, 0, width, height, Color::rgb(120, 0, 0));
+    renderer.draw_text(&font, title, MARGIN, MARGIN, color);
+
+    let columns = ((width - 2 * MARGIN) / CHAR_WIDTH) as usize;
+    let mut y = MARGIN + 2 * LINE_HEIGHT;
+    for line in lines.iter() {
+        // wrap long lines, e.g. the panic message or long file paths
+        let chars: Vec<char> = line.chars().collect();
+        for part in chars.chunks(columns) {
+            if y + LINE_HEIGHT > height {
+                return;
+            }
+            let text: String = part.iter().cloned().collect();
+            renderer.draw_text(&font, &text, MARGIN, y, color);
+            y += LINE_HEIGHT;
+        }
+    }
+}
+
+/// Shows the crash screen and writes the same text over semihosting if a
+/// debugger is attached. Returns false on a crash while handling a crash.
+pub fn report(title: &str, lines: &[String]) -> bool {
+    if CRASHED.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+
+    error!("{}", title);
+    for line in lines.iter() {
+        error!("{}", line);
+    }
+
+    if semihosting::is_debugger_attached() {
+        let mut text = String::from(title);
+        text.push('\n');
+        for line in lines.iter() {
+            text.push_str(line);
+            text.push('\n');
+        }
+        semihosting::write(semihosting::STDERR, text.as_bytes());
+    }
+
+    unsafe {
+        if let Some(ref screen) = CRASH_SCREEN {
+            (screen.draw)(screen.renderer, screen.font_data, title, lines);
+        }
+    }
+    true
+}
+
+/// Reads the words at the top of the stack.
+pub fn read_stack(sp: u32) -> [u32; STACK_WORDS] {
+    let mut words = [0; STACK_WORDS];
+    let stack = sp as *const u32;
+    for (i, word) in words.iter_mut().enumerate() {
+        *word = unsafe { ptr::read_volatile(stack.offset(i as isize)) };
+    }
+    words
+}
+
+/// Formats the registers, four per line, followed by the top of the stack.
+pub fn register_summary(registers: &[(&str, u32)], sp: u32, stack: &[u32]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for group in registers.chunks(4) {
+        let mut line = String::new();
+        for &(name, value) in group.iter() {
+            let _ = write!(line, "{:<4}{:08x} ", name, value);
+        }
+        lines.push(line);
+    }
+
+    for (row, words) in stack.chunks(4).enumerate() {
+        let mut line = String::new();
+        let _ = write!(line, "{:08x}:", sp as usize + row * 16);
+        for word in words.iter() {
+            let _ = write!(line, " {:08x}", word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Returns the panic that happened before the last reset, only once.
+pub fn take_last_panic() -> Option<PanicRecord> {
+    unsafe {
+        let record = ptr::read_volatile(&LAST_PANIC);
+        if record.magic != MAGIC {
+            return None;
+        }
+        ptr::write_volatile(&mut LAST_PANIC.magic, 0);
+        Some(record)
+    }
+}
+
+impl PanicRecord {
+    /// The message, the location and the registers.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        lines.push(String::from(text(&self.message, self.message_len)));
+        lines.push(String::from(text(&self.location, self.location_len)));
+        lines.push(String::new());
+        lines.extend(register_summary(&[("sp", self.sp), ("lr", self.lr)], self.sp, &self.stack));
+        lines
+    }
+}
+
+/// Copies as much of `text` into `buffer` as fits without splitting a
+/// character and returns the number of bytes copied.
+fn copy_text(text: &str, buffer: &mut [u8]) -> usize {
+    let mut len = text.len().min(buffer.len());
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    buffer[..len].copy_from_slice(&text.as_bytes()[..len]);
+    len
+}
+
+fn text(buffer: &[u8], len: usize) -> &str {
+    str::from_utf8(&buffer[..len.min(buffer.len())]).unwrap_or("?")
+}
+
+/// Stores the panic for the next start and shows the crash screen. The
+/// `panic_fmt` lang item is defined by the board crate, so this has to be
+/// called from its handler, a second definition here would clash with it.
+pub fn report_panic(message: fmt::Arguments, file: &str, line: u32, column: u32) -> ! {
+    let sp: u32;
+    let lr: u32;
+    unsafe {
+        asm!("mov $0, sp" : "=r"(sp) ::: "volatile");
+        asm!("mov $0, lr" : "=r"(lr) ::: "volatile");
+    }
+
+    let mut text = String::new();
+    let _ = text.write_fmt(message);
+    let mut location = String::new();
+    let _ = write!(location, "at {}:{}:{}", file, line, column);
+
+    let mut record = PanicRecord {
+        magic: MAGIC,
+        message: [0; MESSAGE_BYTES],
+        message_len: 0,
+        location: [0; LOCATION_BYTES],
+        location_len: 0,
+        sp: sp,
+        lr: lr,
+        stack: read_stack(sp),
+    };
+    record.message_len = copy_text(&text, &mut record.message);
+    record.location_len = copy_text(&location, &mut record.location);
+    // stored first, the crash screen is gone after a watchdog reset and
+    // drawing it might panic again
+    unsafe {
+        ptr::write_volatile(&mut LAST_PANIC, record);
+    }
+
+    report("PANIC", &record.describe());
+    loop {}
+}
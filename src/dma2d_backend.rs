@@ -0,0 +1,135 @@
+#![allow(dead_code)]
+
+extern crate stm32f7_discovery as stm32f7;
+
+use backend::{uses_dma, Backend, SoftwareBackend, Surface, TransferLayout};
+use stm32f7::board::{dma2d, rcc};
+
+// DMA2D transfer modes (CR.MODE)
+const MODE_MEMORY_TO_MEMORY: u8 = 0b00;
+const MODE_MEMORY_TO_MEMORY_BLEND: u8 = 0b10;
+const MODE_REGISTER_TO_MEMORY: u8 = 0b11;
+
+// ARGB8888 color mode for all input and output layers
+const COLOR_MODE_ARGB8888: u8 = 0b0000;
+
+/// Uses the Chrom-ART accelerator of the STM32F7. All operations wait until
+/// the last transfer is completed.
+pub struct Dma2dBackend {
+    dma2d: &'static mut dma2d::Dma2d,
+}
+
+impl Dma2dBackend {
+    pub fn new(dma2d: &'static mut dma2d::Dma2d, rcc: &mut rcc::Rcc) -> Dma2dBackend {
+        rcc.ahb1enr.update(|r| r.set_dma2den(true));
+        Dma2dBackend { dma2d: dma2d }
+    }
+
+    fn set_output(&mut self, layout: TransferLayout) {
+        self.dma2d.opfccr.update(|r| r.set_cm(COLOR_MODE_ARGB8888));
+        self.dma2d.omar.update(|r| r.set_ma(layout.address as u32));
+        self.dma2d.oor.update(|r| r.set_lo(layout.line_offset));
+        self.dma2d.nlr.update(|r| {
+            r.set_pl(layout.pixels_per_line);
+            r.set_nl(layout.lines);
+        });
+    }
+
+    fn set_foreground(&mut self, layout: TransferLayout) {
+        self.dma2d.fgpfccr.update(|r| {
+            r.set_cm(COLOR_MODE_ARGB8888);
+            // use the alpha value of the pixels
+            r.set_am(0b00);
+        });
+        self.dma2d.fgmar.update(|r| r.set_ma(layout.address as u32));
+        self.dma2d.fgor.update(|r| r.set_lo(layout.line_offset));
+    }
+
+    fn set_background(&mut self, layout: TransferLayout) {
+        self.dma2d.bgpfccr.update(|r| r.set_cm(COLOR_MODE_ARGB8888));
+        self.dma2d.bgmar.update(|r| r.set_ma(layout.address as u32));
+        self.dma2d.bgor.update(|r| r.set_lo(layout.line_offset));
+    }
+
+    fn set_color(&mut self, value: u32) {
+        self.dma2d.ocolr.update(|r| {
+            // the field is misspelled in the SVD file
+            r.set_aplha((value >> 24) as u8);
+            r.set_red((value >> 16) as u8);
+            r.set_green((value >> 8) as u8);
+            r.set_blue(value as u8);
+        });
+    }
+
+    /// Starts a transfer without waiting for it.
+    fn start_async(&mut self, mode: u8) {
+        self.dma2d.cr.update(|r| {
+            r.set_mode(mode);
+            r.set_start(true);
+        });
+    }
+
+    /// Waits until the started transfer is completed.
+    fn wait(&mut self) {
+        while !self.dma2d.isr.read().tcif() {}
+        self.dma2d.ifcr.update(|r| r.set_ctcif(true));
+    }
+
+    fn start(&mut self, mode: u8) {
+        self.start_async(mode);
+        self.wait();
+    }
+}
+
+impl Backend for Dma2dBackend {
+    fn fill(&mut self, target: &Surface, x: i32, y: i32, w: i32, h: i32, value: u32) {
+        if w <= 0 || h <= 0 {
+            return;
+        }
+        self.set_output(TransferLayout::new(target, x, y, w, h));
+        self.set_color(value);
+        self.start(MODE_REGISTER_TO_MEMORY);
+    }
+
+    fn fill_rects(&mut self, target: &Surface, rects: &[(i32, i32, i32, i32)], value: u32) {
+        self.set_color(value);
+        let mut running = false;
+        for &(x, y, w, h) in rects.iter() {
+            if w <= 0 || h <= 0 {
+                continue;
+            }
+            if !uses_dma(w, h) {
+                SoftwareBackend.fill(target, x, y, w, h, value);
+                continue;
+            }
+            if running {
+                self.wait();
+            }
+            self.set_output(TransferLayout::new(target, x, y, w, h));
+            self.start_async(MODE_REGISTER_TO_MEMORY);
+            running = true;
+        }
+        if running {
+            self.wait();
+        }
+    }
+
+    fn copy(&mut self, source: &Surface, target: &Surface, x: i32, y: i32, w: i32, h: i32) {
+        if w <= 0 || h <= 0 {
+            return;
+        }
+        self.set_foreground(TransferLayout::new(source, x, y, w, h));
+        self.set_output(TransferLayout::new(target, x, y, w, h));
+        self.start(MODE_MEMORY_TO_MEMORY);
+    }
+
+    fn blend(&mut self, source: &Surface, sx: i32, sy: i32, target: &Surface, x: i32, y: i32, w: i32, h: i32) {
+        if w <= 0 || h <= 0 {
+            return;
+        }
+        self.set_foreground(TransferLayout::new(source, sx, sy, w, h));
+        self.set_background(TransferLayout::new(target, x, y, w, h));
+        self.set_output(TransferLayout::new(target, x, y, w, h));
+        self.start(MODE_MEMORY_TO_MEMORY_BLEND);
+    }
+}
@@ -0,0 +1,121 @@
+#![allow(dead_code)]
+
+use core::ptr;
+
+use sdram_buffer::SdramBuffer;
+
+// The LCD driver owns the LTDC peripheral, so the few registers needed for
+// swapping buffers are accessed directly.
+const LTDC_BASE: usize = 0x4001_6800;
+const LTDC_SRCR: usize = LTDC_BASE + 0x24;
+const LTDC_LAYER_1: usize = LTDC_BASE + 0x84;
+const LTDC_LAYER_2: usize = LTDC_BASE + 0x104;
+
+// offsets of the layer registers
+const LXPFCR: usize = 0x10;
+const LXCFBAR: usize = 0x28;
+const LXCFBLR: usize = 0x2C;
+
+const SRCR_IMR: u32 = 1 << 0;
+const SRCR_VBR: u32 = 1 << 1;
+
+const PIXEL_FORMAT_ARGB8888: u32 = 0b000;
+
+/// Two full ARGB8888 framebuffers for one LTDC layer plus a buffer holding
+/// the static content (background and everything drawn outside of frames).
+///
+/// A frame is rendered into the back buffer, which is then shown by changing
+/// the layer start address during the next vertical blanking period.
+pub struct DoubleBuffer {
+    layer_base: usize,
+    width: i32,
+    height: i32,
+    buffers: [SdramBuffer; 2],
+    static_buffer: SdramBuffer,
+    back: usize,
+    swap_count: u32,
+}
+
+impl DoubleBuffer {
+    /// Allocates the buffers and switches LTDC layer `layer` (1 or 2) to them.
+    pub fn new(layer: u8, width: i32, height: i32) -> Option<DoubleBuffer> {
+        let size = (width * height) as usize;
+        let buffer_0 = SdramBuffer::allocate(size)?;
+        let buffer_1 = SdramBuffer::allocate(size)?;
+        let static_buffer = SdramBuffer::allocate(size)?;
+
+        let layer_base = if layer == 1 { LTDC_LAYER_1 } else { LTDC_LAYER_2 };
+        let line_bytes = width as u32 * 4;
+        unsafe {
+            write_register(layer_base + LXPFCR, PIXEL_FORMAT_ARGB8888);
+            write_register(layer_base + LXCFBLR, line_bytes << 16 | (line_bytes + 3));
+            write_register(layer_base + LXCFBAR, buffer_0.address() as u32);
+            write_register(LTDC_SRCR, SRCR_IMR);
+        }
+
+        Some(DoubleBuffer {
+            layer_base: layer_base,
+            width: width,
+            height: height,
+            buffers: [buffer_0, buffer_1],
+            static_buffer: static_buffer,
+            back: 1,
+            swap_count: 0,
+        })
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn front_address(&self) -> usize {
+        self.buffers[1 - self.back].address()
+    }
+
+    pub fn back_address(&self) -> usize {
+        self.buffers[self.back].address()
+    }
+
+    pub fn static_address(&self) -> usize {
+        self.static_buffer.address()
+    }
+
+    pub fn swap_count(&self) -> u32 {
+        self.swap_count
+    }
+
+    /// Writes a pixel of the static content, which is visible immediately.
+    pub fn write_static(&mut self, index: usize, value: u32) {
+        self.static_buffer.write(index, value);
+        let front = 1 - self.back;
+        self.buffers[front].write(index, value);
+    }
+
+    pub fn write_back(&mut self, index: usize, value: u32) {
+        self.buffers[self.back].write(index, value);
+    }
+
+    /// Shows the back buffer at the next vertical blanking period and waits for it.
+    pub fn present(&mut self) {
+        unsafe {
+            write_register(self.layer_base + LXCFBAR, self.back_address() as u32);
+            write_register(LTDC_SRCR, SRCR_VBR);
+            // the bit is cleared by hardware once the shadow registers are reloaded
+            while read_register(LTDC_SRCR) & SRCR_VBR != 0 {}
+        }
+        self.back = 1 - self.back;
+        self.swap_count += 1;
+    }
+}
+
+unsafe fn write_register(address: usize, value: u32) {
+    ptr::write_volatile(address as *mut u32, value);
+}
+
+unsafe fn read_register(address: usize) -> u32 {
+    ptr::read_volatile(address as *const u32)
+}
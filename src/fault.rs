@@ -0,0 +1,247 @@
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::Vec;
+use alloc::string::String;
+use core::fmt::Write;
+use core::ptr;
+
+use crash::{self, STACK_WORDS};
+
+const VTOR: usize = 0xE000_ED08;
+const SHCSR: usize = 0xE000_ED24;
+const CFSR: usize = 0xE000_ED28;
+const HFSR: usize = 0xE000_ED2C;
+const MMFAR: usize = 0xE000_ED34;
+const BFAR: usize = 0xE000_ED38;
+
+// enable the MemManage, BusFault and UsageFault exceptions, otherwise they
+// escalate to a HardFault and only the forced bit is set in HFSR
+const SHCSR_FAULTS_ENABLE: u32 = 0b111 << 16;
+
+// 16 system exceptions and the 98 interrupts of the STM32F746
+const VECTOR_COUNT: usize = 16 + 98;
+const HARD_FAULT: usize = 3;
+const MEM_MANAGE: usize = 4;
+const BUS_FAULT: usize = 5;
+const USAGE_FAULT: usize = 6;
+
+// marks a valid record in the uninitialized RAM
+const MAGIC: u32 = 0xFA17_C0DE;
+
+const CFSR_MMARVALID: u32 = 1 << 7;
+const CFSR_BFARVALID: u32 = 1 << 15;
+
+// bits of CFSR (MMFSR, BFSR and UFSR) and what they mean
+static CFSR_REASONS: [(u32, &str); 16] = [
+    (1 << 0, "instruction access violation"),
+    (1 << 1, "data access violation"),
+    (1 << 3, "MPU fault on exception return"),
+    (1 << 4, "MPU fault on exception entry"),
+    (1 << 5, "MPU fault during lazy FP state preservation"),
+    (1 << 8, "instruction bus error"),
+    (1 << 9, "precise data bus error"),
+    (1 << 10, "imprecise data bus error"),
+    (1 << 11, "bus fault on exception return"),
+    (1 << 12, "bus fault on exception entry"),
+    (1 << 13, "bus fault during lazy FP state preservation"),
+    (1 << 16, "undefined instruction"),
+    (1 << 17, "invalid state (Thumb bit cleared)"),
+    (1 << 18, "invalid PC on exception return"),
+    (1 << 19, "no coprocessor, FPU disabled?"),
+    (1 << 24, "unaligned access"),
+];
+
+static HFSR_REASONS: [(u32, &str); 3] = [
+    (1 << 1, "bus fault on vector table read"),
+    (1 << 30, "escalated configurable fault"),
+    (1 << 31, "debug event"),
+];
+
+/// The registers pushed onto the stack on exception entry.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ExceptionFrame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FaultRecord {
+    magic: u32,
+    pub exception: u32,
+    pub frame: ExceptionFrame,
+    pub sp: u32,
+    pub stack: [u32; STACK_WORDS],
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+}
+
+// not touched by the startup code, so the record survives a reset
+#[link_section = ".noinit"]
+static mut LAST_FAULT: FaultRecord = FaultRecord {
+    magic: 0,
+    exception: 0,
+    frame: ExceptionFrame {
+        r0: 0,
+        r1: 0,
+        r2: 0,
+        r3: 0,
+        r12: 0,
+        lr: 0,
+        pc: 0,
+        xpsr: 0,
+    },
+    sp: 0,
+    stack: [0; STACK_WORDS],
+    cfsr: 0,
+    hfsr: 0,
+    mmfar: 0,
+    bfar: 0,
+};
+
+// copy of the vector table, the section is aligned to 512 bytes as VTOR requires
+#[link_section = ".ram_vectors"]
+static mut VECTORS: [u32; VECTOR_COUNT] = [0; VECTOR_COUNT];
+
+/// Moves the vector table to RAM and installs the fault handlers in it.
+pub fn init() {
+    unsafe {
+        let flash_vectors = ptr::read_volatile(VTOR as *const u32) as *const u32;
+        for i in 0..VECTOR_COUNT {
+            VECTORS[i] = ptr::read_volatile(flash_vectors.offset(i as isize));
+        }
+        let handler = fault_trampoline as usize as u32;
+        for &exception in [HARD_FAULT, MEM_MANAGE, BUS_FAULT, USAGE_FAULT].iter() {
+            VECTORS[exception] = handler;
+        }
+        ptr::write_volatile(VTOR as *mut u32, VECTORS.as_ptr() as u32);
+        asm!("dsb\n isb" :::: "volatile");
+
+        let shcsr = ptr::read_volatile(SHCSR as *const u32);
+        ptr::write_volatile(SHCSR as *mut u32, shcsr | SHCSR_FAULTS_ENABLE);
+    }
+}
+
+/// Passes the stack the exception frame was pushed to and the number of the
+/// exception to `handle_fault`.
+#[naked]
+extern "C" fn fault_trampoline() {
+    unsafe {
+        asm!("tst lr, #4
+              ite eq
+              mrseq r0, msp
+              mrsne r0, psp
+              mrs r1, ipsr
+              b handle_fault"
+             :::: "volatile");
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn handle_fault(frame: &ExceptionFrame, exception: u32) -> ! {
+    // the frame of 8 words is popped on exception return, floating point
+    // registers that were stacked as well are not taken into account
+    let sp = frame as *const ExceptionFrame as u32 + 32;
+    let record = unsafe {
+        FaultRecord {
+            magic: MAGIC,
+            exception: exception & 0x1FF,
+            frame: *frame,
+            sp: sp,
+            stack: crash::read_stack(sp),
+            cfsr: ptr::read_volatile(CFSR as *const u32),
+            hfsr: ptr::read_volatile(HFSR as *const u32),
+            mmfar: ptr::read_volatile(MMFAR as *const u32),
+            bfar: ptr::read_volatile(BFAR as *const u32),
+        }
+    };
+    // stored first, drawing the crash screen might fault again
+    unsafe {
+        ptr::write_volatile(&mut LAST_FAULT, record);
+    }
+
+    crash::report(record.name(), &record.describe());
+    loop {}
+}
+
+/// Returns the fault that happened before the last reset, only once.
+pub fn take_last_fault() -> Option<FaultRecord> {
+    unsafe {
+        let record = ptr::read_volatile(&LAST_FAULT);
+        if record.magic != MAGIC {
+            return None;
+        }
+        ptr::write_volatile(&mut LAST_FAULT.magic, 0);
+        Some(record)
+    }
+}
+
+impl FaultRecord {
+    pub fn name(&self) -> &'static str {
+        match self.exception as usize {
+            HARD_FAULT => "HARD FAULT",
+            MEM_MANAGE => "MEMORY MANAGEMENT FAULT",
+            BUS_FAULT => "BUS FAULT",
+            USAGE_FAULT => "USAGE FAULT",
+            _ => "FAULT",
+        }
+    }
+
+    /// The fault reasons, the faulting addresses and the stacked registers.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &(bit, reason) in CFSR_REASONS.iter() {
+            if self.cfsr & bit != 0 {
+                lines.push(String::from(reason));
+            }
+        }
+        for &(bit, reason) in HFSR_REASONS.iter() {
+            if self.hfsr & bit != 0 {
+                lines.push(String::from(reason));
+            }
+        }
+        if self.cfsr & CFSR_MMARVALID != 0 {
+            let mut line = String::new();
+            let _ = write!(line, "at address {:08x}", self.mmfar);
+            lines.push(line);
+        }
+        if self.cfsr & CFSR_BFARVALID != 0 {
+            let mut line = String::new();
+            let _ = write!(line, "at address {:08x}", self.bfar);
+            lines.push(line);
+        }
+        let mut line = String::new();
+        let _ = write!(line, "cfsr {:08x}  hfsr {:08x}", self.cfsr, self.hfsr);
+        lines.push(line);
+        lines.push(String::new());
+
+        let frame = &self.frame;
+        lines.extend(crash::register_summary(
+            &[
+                ("pc", frame.pc),
+                ("lr", frame.lr),
+                ("sp", self.sp),
+                ("psr", frame.xpsr),
+                ("r0", frame.r0),
+                ("r1", frame.r1),
+                ("r2", frame.r2),
+                ("r3", frame.r3),
+                ("r12", frame.r12),
+            ],
+            self.sp,
+            &self.stack,
+        ));
+        lines
+    }
+}
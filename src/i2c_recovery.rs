@@ -0,0 +1,112 @@
+#![allow(dead_code)]
+
+use core::ptr;
+
+use profiler;
+
+// the touch controller is connected to I2C3 on PH7 (SCL) and PH8 (SDA)
+const GPIOH_BASE: usize = 0x4002_1C00;
+const GPIO_MODER: usize = 0x00;
+const GPIO_OTYPER: usize = 0x04;
+const GPIO_IDR: usize = 0x10;
+const GPIO_BSRR: usize = 0x18;
+
+const SCL: u32 = 7;
+const SDA: u32 = 8;
+
+const MODE_OUTPUT: u32 = 0b01;
+const MODE_ALTERNATE: u32 = 0b10;
+const MODE_MASK: u32 = 0b11;
+
+// half of a 100 kHz clock period
+const HALF_PERIOD_US: u32 = 5;
+const CYCLES_PER_US: u32 = profiler::CPU_FREQUENCY / 1_000_000;
+
+/// Frees the I2C3 bus if a device holds SDA low, e.g. because the board was
+/// reset in the middle of a transfer. The device gets clock pulses until it
+/// releases SDA, then a stop condition ends the transfer.
+///
+/// At startup it has to be called before `i2c::init_pins_and_clocks`, which
+/// switches the pins back to their I2C function, later `restore_i2c3_pins`
+/// does that. Needs the cycle counter of `profiler::init` for timing the
+/// clock pulses. Returns false if SDA is still low.
+pub fn recover_i2c3() -> bool {
+    unsafe {
+        // open drain outputs, released (high)
+        set_pin(SCL, true);
+        set_pin(SDA, true);
+        let otyper = read_register(GPIOH_BASE + GPIO_OTYPER);
+        write_register(GPIOH_BASE + GPIO_OTYPER, otyper | 1 << SCL | 1 << SDA);
+        let mut moder = read_register(GPIOH_BASE + GPIO_MODER);
+        moder &= !(MODE_MASK << (2 * SCL) | MODE_MASK << (2 * SDA));
+        moder |= MODE_OUTPUT << (2 * SCL) | MODE_OUTPUT << (2 * SDA);
+        write_register(GPIOH_BASE + GPIO_MODER, moder);
+        delay_us(HALF_PERIOD_US);
+
+        if sda_high() {
+            return true;
+        }
+        warn!("I2C bus is stuck, sending clock pulses");
+
+        // a byte and the acknowledge bit at most
+        for _ in 0..9 {
+            set_pin(SCL, false);
+            delay_us(HALF_PERIOD_US);
+            set_pin(SCL, true);
+            delay_us(HALF_PERIOD_US);
+            if sda_high() {
+                break;
+            }
+        }
+
+        // stop condition: SDA rises while SCL is high
+        set_pin(SCL, false);
+        delay_us(HALF_PERIOD_US);
+        set_pin(SDA, false);
+        delay_us(HALF_PERIOD_US);
+        set_pin(SCL, true);
+        delay_us(HALF_PERIOD_US);
+        set_pin(SDA, true);
+        delay_us(HALF_PERIOD_US);
+
+        let recovered = sda_high();
+        if !recovered {
+            error!("I2C bus is still stuck");
+        }
+        recovered
+    }
+}
+
+/// Switches PH7 and PH8 back to I2C after `recover_i2c3`, once
+/// `i2c::init_pins_and_clocks` selected their alternate function. Used when the
+/// bus is recovered while the program is running.
+pub fn restore_i2c3_pins() {
+    unsafe {
+        let mut moder = read_register(GPIOH_BASE + GPIO_MODER);
+        moder &= !(MODE_MASK << (2 * SCL) | MODE_MASK << (2 * SDA));
+        moder |= MODE_ALTERNATE << (2 * SCL) | MODE_ALTERNATE << (2 * SDA);
+        write_register(GPIOH_BASE + GPIO_MODER, moder);
+    }
+}
+
+unsafe fn sda_high() -> bool {
+    read_register(GPIOH_BASE + GPIO_IDR) & 1 << SDA != 0
+}
+
+unsafe fn set_pin(pin: u32, high: bool) {
+    let bit = if high { 1 << pin } else { 1 << (pin + 16) };
+    write_register(GPIOH_BASE + GPIO_BSRR, bit);
+}
+
+fn delay_us(us: u32) {
+    let start = profiler::cycles();
+    while profiler::cycles().wrapping_sub(start) < us * CYCLES_PER_US {}
+}
+
+unsafe fn write_register(address: usize, value: u32) {
+    ptr::write_volatile(address as *mut u32, value);
+}
+
+unsafe fn read_register(address: usize) -> u32 {
+    ptr::read_volatile(address as *const u32)
+}
@@ -0,0 +1,85 @@
+#![allow(dead_code)]
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Every pixel is an ARGB8888 value stored as four bytes
+    Rgba,
+    /// Every pixel is a byte indexing the ARGB8888 palette
+    Indexed,
+}
+
+/// A bitmap, usually generated from `assets/` by the build script.
+///
+/// Compressed data is a sequence of runs, each a count byte followed by one
+/// pixel that is repeated count times.
+pub struct Image {
+    pub width: i32,
+    pub height: i32,
+    pub format: ImageFormat,
+    pub compressed: bool,
+    pub palette: &'static [u32],
+    pub data: &'static [u8],
+}
+
+impl Image {
+    /// Iterates over the ARGB8888 pixel values row by row.
+    pub fn pixels(&self) -> Pixels {
+        Pixels {
+            image: self,
+            position: 0,
+            remaining: 0,
+            value: 0,
+        }
+    }
+
+    fn pixel_size(&self) -> usize {
+        match self.format {
+            ImageFormat::Rgba => 4,
+            ImageFormat::Indexed => 1,
+        }
+    }
+
+    fn read_pixel(&self, position: usize) -> u32 {
+        match self.format {
+            ImageFormat::Rgba => {
+                let d = &self.data[position..position + 4];
+                (d[0] as u32) << 24 | (d[1] as u32) << 16 | (d[2] as u32) << 8 | d[3] as u32
+            }
+            ImageFormat::Indexed => self.palette[self.data[position] as usize],
+        }
+    }
+}
+
+pub struct Pixels<'a> {
+    image: &'a Image,
+    position: usize,
+    remaining: u8,
+    value: u32,
+}
+
+impl<'a> Iterator for Pixels<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        let size = self.image.pixel_size();
+        if !self.image.compressed {
+            if self.position + size > self.image.data.len() {
+                return None;
+            }
+            let value = self.image.read_pixel(self.position);
+            self.position += size;
+            return Some(value);
+        }
+
+        if self.remaining == 0 {
+            if self.position + 1 + size > self.image.data.len() {
+                return None;
+            }
+            self.remaining = self.image.data[self.position];
+            self.value = self.image.read_pixel(self.position + 1);
+            self.position += 1 + size;
+        }
+        self.remaining -= 1;
+        Some(self.value)
+    }
+}
@@ -0,0 +1,332 @@
+#![allow(dead_code)]
+
+extern crate stm32f7_discovery as stm32f7;
+extern crate alloc;
+
+use alloc::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use stm32f7::interrupts::InterruptRequest;
+use stm32f7::system_clock;
+
+use touch_input::{TouchInput, TouchPoint};
+use touch_interrupt;
+
+const GPIO_BASE: usize = 0x4002_0000;
+const GPIO_PORT_SIZE: usize = 0x400;
+const GPIO_MODER: usize = 0x00;
+const GPIO_PUPDR: usize = 0x0C;
+const GPIO_IDR: usize = 0x10;
+
+const PULL_UP: u32 = 0b01;
+
+const RCC_APB2ENR: usize = 0x4002_3844;
+const SYSCFGEN: u32 = 1 << 14;
+
+// the external interrupt lines are connected to a port in EXTICR1 to EXTICR4
+const SYSCFG_EXTICR1: usize = 0x4001_3800 + 0x08;
+
+const EXTI_BASE: usize = 0x4001_3C00;
+const EXTI_IMR: usize = EXTI_BASE + 0x00;
+const EXTI_RTSR: usize = EXTI_BASE + 0x08;
+const EXTI_FTSR: usize = EXTI_BASE + 0x0C;
+const EXTI_PR: usize = EXTI_BASE + 0x14;
+
+// a button has to keep its state this long, edges in between are bounces
+const DEBOUNCE_MS: usize = 20;
+
+/// The blue user button, it is pulled down on the board.
+pub const USER_BUTTON: ButtonPin = ButtonPin {
+    port: Port::I,
+    pin: 11,
+    active_low: false,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Port {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+}
+
+/// An input on a GPIO pin, e.g. a push button. Active low inputs connect the
+/// pin to ground and use the internal pull-up.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ButtonPin {
+    pub port: Port,
+    pub pin: u8,
+    pub active_low: bool,
+}
+
+impl ButtonPin {
+    fn base(&self) -> usize {
+        GPIO_BASE + self.port as usize * GPIO_PORT_SIZE
+    }
+
+    fn is_pressed(&self) -> bool {
+        let idr = unsafe { ptr::read_volatile((self.base() + GPIO_IDR) as *const u32) };
+        let high = idr & (1 << self.pin) != 0;
+        high != self.active_low
+    }
+
+    /// The interrupt that handles the external interrupt line of the pin.
+    pub fn interrupt_request(&self) -> InterruptRequest {
+        match self.pin {
+            0 => InterruptRequest::Exti0,
+            1 => InterruptRequest::Exti1,
+            2 => InterruptRequest::Exti2,
+            3 => InterruptRequest::Exti3,
+            4 => InterruptRequest::Exti4,
+            5...9 => InterruptRequest::Exti9_5,
+            _ => InterruptRequest::Exti15_10,
+        }
+    }
+}
+
+/// The edges of a pin that trigger its external interrupt line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Edges {
+    Rising,
+    Falling,
+    Both,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Source {
+    Touch,
+    UserButton,
+    ExternalButton,
+}
+
+/// A press of the touchscreen or of a button. Only touches have a position,
+/// in panel coordinates. `ms` is taken in the interrupt that noticed the
+/// press, or when it was polled if the interrupts are not enabled.
+#[derive(Clone, Copy)]
+pub struct Tap {
+    pub source: Source,
+    pub position: Option<TouchPoint>,
+    pub ms: usize,
+}
+
+/// The debounced state of a button, updated by the interrupt handler and
+/// by polling.
+struct ButtonState {
+    pressed: AtomicBool,
+    last_change_ms: AtomicUsize,
+    presses: AtomicUsize,
+    last_press_ms: AtomicUsize,
+}
+
+impl ButtonState {
+    fn update(&self, pressed: bool, ms: usize) {
+        if pressed == self.pressed.load(Ordering::SeqCst)
+            || ms - self.last_change_ms.load(Ordering::SeqCst) < DEBOUNCE_MS
+        {
+            return;
+        }
+        self.pressed.store(pressed, Ordering::SeqCst);
+        self.last_change_ms.store(ms, Ordering::SeqCst);
+        if pressed {
+            self.last_press_ms.store(ms, Ordering::SeqCst);
+            self.presses.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+static USER_BUTTON_STATE: ButtonState = ButtonState {
+    pressed: AtomicBool::new(false),
+    last_change_ms: AtomicUsize::new(0),
+    presses: AtomicUsize::new(0),
+    last_press_ms: AtomicUsize::new(0),
+};
+static EXTERNAL_BUTTON_STATE: ButtonState = ButtonState {
+    pressed: AtomicBool::new(false),
+    last_change_ms: AtomicUsize::new(0),
+    presses: AtomicUsize::new(0),
+    last_press_ms: AtomicUsize::new(0),
+};
+
+// only written by `Input::new` before the interrupts are enabled
+static mut EXTERNAL_BUTTON: Option<ButtonPin> = None;
+
+/// Merges the touchscreen, the user button and an optional external button
+/// into one stream of taps.
+pub struct Input {
+    touch: TouchInput,
+    touches: Vec<TouchPoint>,
+    user_presses: usize,
+    external_presses: usize,
+}
+
+impl Input {
+    /// Configures the button pins and their external interrupt lines. The
+    /// buttons work without interrupts as well, but then a press is only seen
+    /// by `poll` and timestamped late.
+    pub fn new(touch: TouchInput, external_button: Option<ButtonPin>) -> Input {
+        unsafe {
+            let apb2enr = ptr::read_volatile(RCC_APB2ENR as *const u32);
+            ptr::write_volatile(RCC_APB2ENR as *mut u32, apb2enr | SYSCFGEN);
+
+            init_button(&USER_BUTTON);
+            if let Some(button) = external_button {
+                if button.pin == USER_BUTTON.pin || button.pin == touch_interrupt::INT_PIN.pin {
+                    // both would need the same external interrupt line
+                    error!("the external button can't use pin {}", button.pin);
+                } else {
+                    init_button(&button);
+                    EXTERNAL_BUTTON = Some(button);
+                    info!("external button on P{:?}{}", button.port, button.pin);
+                }
+            }
+        }
+
+        Input {
+            touch: touch,
+            touches: Vec::new(),
+            user_presses: USER_BUTTON_STATE.presses.load(Ordering::SeqCst),
+            external_presses: EXTERNAL_BUTTON_STATE.presses.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn is_touch_available(&self) -> bool {
+        self.touch.is_touch_available()
+    }
+
+    pub fn enable_touch_interrupt(&mut self) -> bool {
+        self.touch.enable_interrupt()
+    }
+
+    /// The touches of the last poll, in panel coordinates.
+    pub fn touches(&self) -> &[TouchPoint] {
+        &self.touches
+    }
+
+    /// Returns true while the screen is touched or a button is held down.
+    pub fn is_pressed(&self) -> bool {
+        !self.touches.is_empty() || USER_BUTTON_STATE.pressed.load(Ordering::SeqCst)
+            || EXTERNAL_BUTTON_STATE.pressed.load(Ordering::SeqCst)
+    }
+
+    /// Reads the touch controller and returns the taps since the last poll.
+    pub fn poll(&mut self) -> Vec<Tap> {
+        let ms = system_clock::ticks();
+        update_buttons(ms);
+
+        let mut taps = Vec::new();
+        self.touches = self.touch.poll();
+        for down in self.touch.take_touch_downs().iter() {
+            taps.push(Tap {
+                source: Source::Touch,
+                position: Some(down.position),
+                ms: down.ms,
+            });
+        }
+
+        let presses = USER_BUTTON_STATE.presses.load(Ordering::SeqCst);
+        if presses != self.user_presses {
+            self.user_presses = presses;
+            taps.push(Tap {
+                source: Source::UserButton,
+                position: None,
+                ms: USER_BUTTON_STATE.last_press_ms.load(Ordering::SeqCst),
+            });
+        }
+        let presses = EXTERNAL_BUTTON_STATE.presses.load(Ordering::SeqCst);
+        if presses != self.external_presses {
+            self.external_presses = presses;
+            taps.push(Tap {
+                source: Source::ExternalButton,
+                position: None,
+                ms: EXTERNAL_BUTTON_STATE.last_press_ms.load(Ordering::SeqCst),
+            });
+        }
+        taps
+    }
+}
+
+/// Handles the external interrupt lines of both buttons. Has to be registered
+/// for `USER_BUTTON.interrupt_request()` and the request of the external button.
+pub fn handle_interrupt() {
+    let mut lines = 1 << USER_BUTTON.pin;
+    if let Some(button) = unsafe { EXTERNAL_BUTTON } {
+        lines |= 1 << button.pin;
+    }
+    unsafe {
+        let pending = ptr::read_volatile(EXTI_PR as *const u32) & lines;
+        if pending == 0 {
+            return;
+        }
+        // cleared by writing 1
+        ptr::write_volatile(EXTI_PR as *mut u32, pending);
+    }
+    update_buttons(system_clock::ticks());
+}
+
+fn update_buttons(ms: usize) {
+    USER_BUTTON_STATE.update(USER_BUTTON.is_pressed(), ms);
+    if let Some(button) = unsafe { EXTERNAL_BUTTON } {
+        EXTERNAL_BUTTON_STATE.update(button.is_pressed(), ms);
+    }
+}
+
+/// Makes the pin an input and triggers its external interrupt line on both edges.
+unsafe fn init_button(button: &ButtonPin) {
+    init_interrupt_pin(button, Edges::Both);
+    set_line_enabled(button, true);
+}
+
+/// Makes the pin an input, pulled up if it is active low, and connects it to
+/// its external interrupt line, which triggers on `edges`. The line stays
+/// masked until `set_line_enabled`. The SYSCFG clock is enabled by `Input::new`.
+pub unsafe fn init_interrupt_pin(pin: &ButtonPin, edges: Edges) {
+    let line = pin.pin as usize;
+    let base = pin.base();
+
+    let moder = ptr::read_volatile((base + GPIO_MODER) as *const u32);
+    ptr::write_volatile((base + GPIO_MODER) as *mut u32, moder & !(0b11 << (2 * line)));
+    let pull = if pin.active_low { PULL_UP } else { 0 };
+    let pupdr = ptr::read_volatile((base + GPIO_PUPDR) as *const u32) & !(0b11 << (2 * line));
+    ptr::write_volatile((base + GPIO_PUPDR) as *mut u32, pupdr | pull << (2 * line));
+
+    let exticr = SYSCFG_EXTICR1 + line / 4 * 4;
+    let shift = line % 4 * 4;
+    let value = ptr::read_volatile(exticr as *const u32) & !(0xF << shift);
+    ptr::write_volatile(exticr as *mut u32, value | (pin.port as u32) << shift);
+
+    let rising = edges != Edges::Falling;
+    let falling = edges != Edges::Rising;
+    for &(register, enabled) in [(EXTI_RTSR, rising), (EXTI_FTSR, falling)].iter() {
+        let value = ptr::read_volatile(register as *const u32) & !(1 << line);
+        ptr::write_volatile(register as *mut u32, if enabled { value | 1 << line } else { value });
+    }
+}
+
+/// Unmasks or masks the external interrupt line of the pin.
+pub fn set_line_enabled(pin: &ButtonPin, enabled: bool) {
+    unsafe {
+        let imr = ptr::read_volatile(EXTI_IMR as *const u32) & !(1 << pin.pin);
+        ptr::write_volatile(EXTI_IMR as *mut u32, if enabled { imr | 1 << pin.pin } else { imr });
+    }
+}
+
+/// Clears the pending bit of the external interrupt line of the pin and
+/// returns whether it was set. Lines 5 to 15 share their interrupts, so only
+/// the bit of this line is touched.
+pub fn take_pending(pin: &ButtonPin) -> bool {
+    unsafe {
+        if ptr::read_volatile(EXTI_PR as *const u32) & 1 << pin.pin == 0 {
+            return false;
+        }
+        // cleared by writing 1
+        ptr::write_volatile(EXTI_PR as *mut u32, 1 << pin.pin);
+    }
+    true
+}
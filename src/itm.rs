@@ -0,0 +1,83 @@
+#![allow(dead_code)]
+
+use core::ptr;
+
+// the stimulus ports are consecutive words starting at the ITM base
+const ITM_BASE: usize = 0xE000_0000;
+const ITM_TER: usize = ITM_BASE + 0xE00;
+const ITM_TCR: usize = ITM_BASE + 0xE80;
+const ITM_LAR: usize = ITM_BASE + 0xFB0;
+
+const DEMCR: usize = 0xE000_EDFC;
+const DBGMCU_CR: usize = 0xE004_2004;
+
+const TPIU_ACPR: usize = 0xE004_0010;
+const TPIU_SPPR: usize = 0xE004_00F0;
+const TPIU_FFCR: usize = 0xE004_0304;
+
+const UNLOCK: u32 = 0xC5AC_CE55;
+const DEMCR_TRCENA: u32 = 1 << 24;
+const DBGMCU_TRACE_IOEN: u32 = 1 << 5;
+const TCR_ITMENA: u32 = 1 << 0;
+const TCR_SYNCENA: u32 = 1 << 2;
+const TCR_SWOENA: u32 = 1 << 4;
+const TCR_TRACE_BUS_ID: u32 = 1 << 16;
+const SPPR_NRZ: u32 = 2;
+
+/// Routes the ITM stimulus ports to the SWO pin (PB3, which is in its trace
+/// function after reset) with the given baud rate.
+pub fn init(cpu_frequency: u32, baud_rate: u32) {
+    unsafe {
+        let demcr = read_register(DEMCR);
+        write_register(DEMCR, demcr | DEMCR_TRCENA);
+        let dbgmcu = read_register(DBGMCU_CR);
+        write_register(DBGMCU_CR, dbgmcu | DBGMCU_TRACE_IOEN);
+
+        write_register(TPIU_SPPR, SPPR_NRZ);
+        write_register(TPIU_ACPR, cpu_frequency / baud_rate - 1);
+        // no formatter, the stream only contains ITM packets
+        write_register(TPIU_FFCR, 0x100);
+
+        write_register(ITM_LAR, UNLOCK);
+        write_register(ITM_TCR, TCR_TRACE_BUS_ID | TCR_SWOENA | TCR_SYNCENA | TCR_ITMENA);
+        write_register(ITM_TER, 0xFFFF_FFFF);
+    }
+}
+
+pub fn is_enabled(port: u8) -> bool {
+    unsafe {
+        read_register(ITM_TCR) & TCR_ITMENA != 0 && read_register(ITM_TER) & (1 << port) != 0
+    }
+}
+
+/// Writes the bytes to a stimulus port, packing them into words where possible.
+/// Does nothing if the port is disabled, e.g. because the trace was never set up.
+pub fn write(port: u8, data: &[u8]) {
+    if !is_enabled(port) {
+        return;
+    }
+    let address = ITM_BASE + 4 * port as usize;
+    for chunk in data.chunks(4) {
+        unsafe {
+            // a stimulus port reads as zero while its FIFO is full
+            while read_register(address) & 1 == 0 {}
+            if chunk.len() == 4 {
+                let word = chunk[0] as u32 | (chunk[1] as u32) << 8 | (chunk[2] as u32) << 16 | (chunk[3] as u32) << 24;
+                write_register(address, word);
+            } else {
+                for &byte in chunk.iter() {
+                    while read_register(address) & 1 == 0 {}
+                    ptr::write_volatile(address as *mut u8, byte);
+                }
+            }
+        }
+    }
+}
+
+unsafe fn write_register(address: usize, value: u32) {
+    ptr::write_volatile(address as *mut u32, value);
+}
+
+unsafe fn read_register(address: usize) -> u32 {
+    ptr::read_volatile(address as *const u32)
+}
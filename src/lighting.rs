@@ -0,0 +1,118 @@
+#![allow(dead_code)]
+
+extern crate stm32f7_discovery as stm32f7;
+
+use stm32f7::lcd::Color;
+
+use renderer::weight_color;
+
+// face normals in tower space (y grows downwards like the screen)
+const LEFT_NORMAL: (f32, f32, f32) = (0f32, 0f32, -1f32);
+const RIGHT_NORMAL: (f32, f32, f32) = (1f32, 0f32, 0f32);
+const TOP_NORMAL: (f32, f32, f32) = (0f32, -1f32, 0f32);
+
+#[derive(Clone, Copy)]
+pub struct FaceColors {
+    pub left_top: Color,
+    pub left_bottom: Color,
+    pub right_top: Color,
+    pub right_bottom: Color,
+    pub top: Color,
+}
+
+impl FaceColors {
+    pub fn map<F: Fn(Color) -> Color>(&self, f: F) -> FaceColors {
+        FaceColors {
+            left_top: f(self.left_top),
+            left_bottom: f(self.left_bottom),
+            right_top: f(self.right_top),
+            right_bottom: f(self.right_bottom),
+            top: f(self.top),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Light {
+    /// Direction pointing towards the light, does not need to be normalized
+    pub direction: (f32, f32, f32),
+    pub ambient: f32,
+    pub diffuse: f32,
+    /// Brightness lost from the top to the bottom edge of a side face
+    pub gradient: f32,
+    /// Brightness lost by the lowest layer of the tower
+    pub depth_darkening: f32,
+    /// Number of layers over which the depth darkening fades out
+    pub depth_layers: usize,
+}
+
+impl Light {
+    pub fn new(direction: (f32, f32, f32), ambient: f32, diffuse: f32) -> Light {
+        Light {
+            direction: direction,
+            ambient: ambient,
+            diffuse: diffuse,
+            gradient: 0f32,
+            depth_darkening: 0f32,
+            depth_layers: 1,
+        }
+    }
+
+    pub fn intensity(&self, normal: (f32, f32, f32)) -> f32 {
+        let (x, y, z) = self.direction;
+        let length = sqrt(x * x + y * y + z * z);
+        let mut dot = 0f32;
+        if length > 0f32 {
+            dot = (normal.0 * x + normal.1 * y + normal.2 * z) / length;
+        }
+        if dot < 0f32 {
+            dot = 0f32;
+        }
+        clamp(self.ambient + self.diffuse * dot)
+    }
+
+    pub fn layer_factor(&self, layer: usize) -> f32 {
+        if layer >= self.depth_layers {
+            return 1f32;
+        }
+        let remaining = (self.depth_layers - layer) as f32 / self.depth_layers as f32;
+        clamp(1f32 - self.depth_darkening * remaining)
+    }
+
+    pub fn shade(&self, base_color: Color, layer: usize) -> FaceColors {
+        let layer_factor = self.layer_factor(layer);
+        let left = self.intensity(LEFT_NORMAL) * layer_factor;
+        let right = self.intensity(RIGHT_NORMAL) * layer_factor;
+        let top = self.intensity(TOP_NORMAL) * layer_factor;
+        let bottom = 1f32 - self.gradient;
+
+        FaceColors {
+            left_top: weight_color(base_color, left),
+            left_bottom: weight_color(base_color, left * bottom),
+            right_top: weight_color(base_color, right),
+            right_bottom: weight_color(base_color, right * bottom),
+            top: weight_color(base_color, top),
+        }
+    }
+}
+
+fn clamp(value: f32) -> f32 {
+    if value < 0f32 {
+        0f32
+    } else if value > 1f32 {
+        1f32
+    } else {
+        value
+    }
+}
+
+fn sqrt(value: f32) -> f32 {
+    if value <= 0f32 {
+        return 0f32;
+    }
+    let mut guess = if value > 1f32 { value } else { 1f32 };
+    for _ in 0..8 {
+        guess = 0.5f32 * (guess + value / guess);
+    }
+    guess
+}
@@ -0,0 +1,77 @@
+#![allow(dead_code)]
+
+extern crate stm32f7_discovery as stm32f7;
+extern crate alloc;
+extern crate log;
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::{LevelFilter, Log, Metadata, Record};
+use stm32f7::system_clock;
+
+use itm;
+use semihosting;
+
+/// Stimulus port that carries the log lines, `log_decoder.py` reads this one.
+pub const ITM_PORT: u8 = 0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Slow, every line stops the core until gdb has handled the breakpoint
+    Semihosting,
+    /// Needs `itm::init` and a probe that captures the SWO pin
+    Itm,
+}
+
+static OUTPUT: AtomicUsize = AtomicUsize::new(Output::Itm as usize);
+static LOGGER: Logger = Logger;
+
+/// Writes every record as one line `<ticks> <LEVEL> <tag>: <message>`, where
+/// the tag is the last part of the module path unless a target is given.
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let tag = record.target().rsplit("::").next().unwrap_or("");
+        let mut line = String::new();
+        if write!(line, "{} {} {}: {}\n", system_clock::ticks(), record.level(), tag, record.args()).is_err() {
+            return;
+        }
+        match output() {
+            Output::Semihosting => {
+                semihosting::write(semihosting::STDERR, line.as_bytes());
+            }
+            Output::Itm => itm::write(ITM_PORT, line.as_bytes()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger, later calls only change the output and level.
+pub fn init(output: Output, level: LevelFilter) {
+    set_output(output);
+    // fails if the logger is installed already, which is fine
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
+
+pub fn set_output(output: Output) {
+    OUTPUT.store(output as usize, Ordering::SeqCst);
+}
+
+pub fn output() -> Output {
+    if OUTPUT.load(Ordering::SeqCst) == Output::Semihosting as usize {
+        Output::Semihosting
+    } else {
+        Output::Itm
+    }
+}
@@ -5,9 +5,13 @@
 #![feature(fnbox)]
 #![feature(unboxed_closures)]
 #![feature(conservative_impl_trait)]
+#![feature(asm)]
+#![feature(naked_functions)]
 
 extern crate alloc;
 extern crate compiler_builtins;
+#[macro_use]
+extern crate log;
 extern crate r0;
 extern crate stm32f7_discovery as stm32f7;
 
@@ -16,18 +20,114 @@ use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::string::ToString;
 use stm32f7::lcd::font::FontRenderer;
-use stm32f7::{board, embedded, lcd, sdram, system_clock, touch, i2c, lcd::Color};
+use stm32f7::{board, embedded, lcd, sdram, system_clock, i2c, lcd::Color};
+use stm32f7::interrupts::{self, InterruptRequest, Priority};
 
 const FPS: i32 = 60;
+// Render the moving block with two full framebuffers instead of the pixel diff
+const DOUBLE_BUFFERING: bool = false;
+// Show the number of missed vertical blanking periods
+const DEBUG_OVERLAY: bool = false;
+// Semihosting only works while gdb is attached
+const LOG_OUTPUT: logger::Output = logger::Output::Itm;
+const SWO_BAUD_RATE: u32 = 2_000_000;
+// Save a screenshot on a three finger tap, needs gdb with semihosting-enable
+const SCREENSHOT_GESTURE: bool = false;
+// The board resets if a frame takes longer than this
+const WATCHDOG_TIMEOUT_MS: u32 = 2000;
+// Frames over which the profiler overlay shows min/avg/max
+const PROFILER_WINDOW: u32 = 30;
+// A touch only cuts if no second finger follows within this time, two fingers
+// toggle the profiler overlay
+const TWO_FINGER_DELAY_MS: usize = 40;
+// Two players stack side by side on the landscape screen
+const VERSUS_MODE: bool = false;
+// Screen rotation, PortraitFlipped if the board is mounted upside down
+const ORIENTATION: Orientation = Orientation::Portrait;
+const VERSUS_ORIENTATION: Orientation = Orientation::Landscape;
+// Read the touch controller when it signals new touches instead of every frame
+const TOUCH_INTERRUPT: bool = true;
+// Arcade button between Arduino header D2 and GND, None if there is none
+const EXTERNAL_BUTTON: Option<ButtonPin> = Some(ButtonPin {
+    port: Port::G,
+    pin: 6,
+    active_low: true,
+});
 
 static TTF: &[u8] = include_bytes!("../RobotoMono-Bold.ttf");
 
 mod renderer;
-use renderer::{hsv_color, weight_color, Renderer};
+use renderer::{Rect, Renderer};
+
+mod raster;
 
 mod block;
 use block::Block;
 
+mod tower;
+use tower::Tower;
+
+mod background;
+use background::Background;
+
+mod lighting;
+
+mod backend;
+mod dma2d_backend;
+use dma2d_backend::Dma2dBackend;
+
+mod vsync;
+
+mod panel;
+
+mod double_buffer;
+use double_buffer::DoubleBuffer;
+
+mod sdram_buffer;
+use sdram_buffer::SdramBuffer;
+
+mod pixel_format;
+use pixel_format::{PanelFormat, PixelFormat};
+
+mod image;
+
+mod assets;
+
+mod theme;
+use theme::{Theme, THEME_COUNT};
+
+mod orientation;
+use orientation::Orientation;
+
+mod profiler;
+use profiler::{Profiler, Section};
+
+mod semihosting;
+
+mod itm;
+
+mod logger;
+
+mod composite;
+
+mod screenshot;
+
+mod crash;
+
+mod fault;
+
+mod watchdog;
+
+mod i2c_recovery;
+
+mod touch_input;
+use touch_input::TouchInput;
+
+mod input;
+use input::{ButtonPin, Input, Port, Source};
+
+mod touch_interrupt;
+
 #[no_mangle]
 pub unsafe extern "C" fn reset() -> ! {
     extern "C" {
@@ -50,6 +150,8 @@ pub unsafe extern "C" fn reset() -> ! {
     // zeroes the .bss section
     r0::zero_bss(bss_start, bss_end);
 
+    watchdog::read_reset_flags();
+
     stm32f7::heap::init();
 
     // Initialize the floating point unit
@@ -80,6 +182,8 @@ fn main(hw: board::Hardware) -> ! {
         gpio_j,
         gpio_k,
         i2c_3,
+        dma2d,
+        nvic,
         ..
     } = hw;
 
@@ -99,6 +203,12 @@ fn main(hw: board::Hardware) -> ! {
 
     system_clock::init(rcc, pwr, flash);
 
+    fault::init();
+
+    itm::init(profiler::CPU_FREQUENCY, SWO_BAUD_RATE);
+    logger::init(LOG_OUTPUT, log::LevelFilter::Info);
+    info!("starting after reset by {}", watchdog::reset_cause().description());
+
     // enable all gpio ports
     rcc.ahb1enr.update(|r| {
         r.set_gpioaen(true);
@@ -128,267 +238,592 @@ fn main(hw: board::Hardware) -> ! {
     layer_2.clear();
     //lcd::init_stdout(layer_2);
 
+    profiler::init();
+
     // i2c
+    i2c_recovery::recover_i2c3();
     i2c::init_pins_and_clocks(rcc, &mut gpio);
-    let mut i2c_3 = i2c::init(i2c_3);
-    i2c_3.test_1();
-    i2c_3.test_2();
+    let i2c_3 = i2c::init(i2c_3);
+    let mut input = Input::new(TouchInput::new(i2c_3), EXTERNAL_BUTTON);
 
-    touch::check_family_id(&mut i2c_3).unwrap();
-
-    let black_bg = move |_x, _y| bg_color;
-    let transparent_bg = |_x, _y| Color::rgba(0, 0, 0, 0);
+    let black_bg = move |_x: i32, _y: i32| bg_color;
+    let transparent_bg = |_x: i32, _y: i32| Color::rgba(0, 0, 0, 0);
     let mut renderer = Renderer::new(&mut layer_1, Box::new(black_bg));
-    let mut top_renderer = Renderer::new(&mut layer_2, Box::new(transparent_bg));
+    let (panel_width, panel_height) = renderer.get_panel_size();
+    let top_double_buffer = if DOUBLE_BUFFERING {
+        DoubleBuffer::new(2, panel_width, panel_height)
+    } else {
+        None
+    };
+    let mut top_renderer =
+        Renderer::with_double_buffer(&mut layer_2, Box::new(transparent_bg), top_double_buffer);
 
-    renderer.set_portrait(true);
-    top_renderer.set_portrait(true);
+    renderer.set_panel_format(PanelFormat::discovery(PixelFormat::Argb8888));
+
+    let orientation = if VERSUS_MODE { VERSUS_ORIENTATION } else { ORIENTATION };
+    renderer.set_orientation(orientation);
+    top_renderer.set_orientation(orientation);
+
+    renderer.set_framebuffer_address(sdram_buffer::LAYER_1_ADDRESS);
+    renderer.set_backend(Box::new(Dma2dBackend::new(dma2d, rcc)));
+    if let Some(buffer) = SdramBuffer::allocate((panel_width * panel_height) as usize) {
+        renderer.enable_bg_cache(buffer);
+    }
+
+    top_renderer.set_vsync(true);
+
+    // the top layer covers everything, so the crash screen is drawn there
+    crash::register(&mut top_renderer, TTF);
+
+    if let Some(fault) = fault::take_last_fault() {
+        let mut title = String::from(fault.name());
+        title.push_str(" before the last reset");
+        error!("{}", title);
+        show_until_tap(&mut top_renderer, &mut input, &title, &fault.describe());
+    } else if let Some(panic) = crash::take_last_panic() {
+        let title = "PANIC before the last reset";
+        error!("{}", title);
+        show_until_tap(&mut top_renderer, &mut input, title, &panic.describe());
+    } else if watchdog::reset_cause().is_watchdog() {
+        let cause = watchdog::reset_cause().description();
+        warn!("reset by {}", cause);
+        let mut line = String::from("reset by ");
+        line.push_str(cause);
+        show_until_tap(&mut top_renderer, &mut input, "RESTARTED", &[line]);
+    }
+
+    renderer.set_antialiasing(true);
+    top_renderer.set_antialiasing(true);
 
     let highscore: &mut i32 = &mut 0;
     let mut round = 0;
+    let mut theme_index = 0;
+
+    interrupts::scope(nvic, |_| {}, |interrupt_table| {
+        interrupt_table
+            .register(InterruptRequest::LcdTft, Priority::P1, vsync::handle_interrupt)
+            .expect("LTDC interrupt already in use");
+        // the user button and the touch controller share the lines 10 to 15
+        interrupt_table
+            .register(InterruptRequest::Exti15_10, Priority::P2, handle_exti15_10)
+            .expect("EXTI 10 to 15 interrupt already in use");
+        if let Some(button) = EXTERNAL_BUTTON {
+            // lines 10 to 15 share the interrupt of the user button
+            if button.pin < 10 {
+                interrupt_table
+                    .register(button.interrupt_request(), Priority::P2, input::handle_interrupt)
+                    .expect("external button interrupt already in use");
+            }
+        }
+        if TOUCH_INTERRUPT {
+            interrupt_table
+                .register(InterruptRequest::I2c3Ev, Priority::P2, touch_interrupt::handle_event)
+                .expect("I2C3 event interrupt already in use");
+            interrupt_table
+                .register(InterruptRequest::I2c3Er, Priority::P2, touch_interrupt::handle_error)
+                .expect("I2C3 error interrupt already in use");
+            input.enable_touch_interrupt();
+        }
+        vsync::init();
+        watchdog::init(WATCHDOG_TIMEOUT_MS);
 
-    loop {
-        let f = get_background(&mut renderer, round);
-        renderer.set_bg(Box::new(f));
-
-        game(&mut renderer, &mut top_renderer, &mut i2c_3, highscore);
+        loop {
+            if VERSUS_MODE {
+                versus(&mut renderer, &mut top_renderer, &mut input, round, theme_index);
+            } else {
+                game(
+                    &mut renderer,
+                    &mut top_renderer,
+                    &mut input,
+                    highscore,
+                    round,
+                    theme_index,
+                );
+            }
+            theme_index = choose_theme(&mut top_renderer, &mut input, theme_index);
 
-        round += 1;
-    }
+            round += 1;
+        }
+    })
 }
 
-fn get_background<T: lcd::Framebuffer>(
-    renderer: &mut Renderer<T>,
-    _round: i32,
-) -> impl FnMut(i32, i32) -> Color {
-    let ymax = renderer.get_height();
-    let bg_color = hsv_color(system_clock::ticks() as f32, 1f32, 0.25f32);
-    move |x, y| {
-        let alpha = y as f32 / ymax as f32;
-        let base_color = weight_color(Color::rgb(0, 0, 64), 1f32 - alpha);
-        let mut color = weight_color(bg_color, alpha);
-        color.red += base_color.red;
-        color.green += base_color.green;
-        color.blue += base_color.blue;
-        if (1329 * (x ^ (y * 717)) + 971) % (ymax - x + 200) == 0 {
-            let mut alpha = y as f32 / ymax as f32;
-            alpha *= alpha;
-            alpha = 1f32 - alpha;
-            color.red = ((1f32 - alpha) * color.red as f32 + alpha * 255f32) as u8;
-            color.green = ((1f32 - alpha) * color.green as f32 + alpha * 255f32) as u8;
-            color.blue = ((1f32 - alpha) * color.blue as f32 + alpha * 255f32) as u8;
-        }
-        fix_color(color)
-    }
+fn get_background(xmax: i32, ymax: i32, round: i32, theme: &Theme) -> Box<Background> {
+    let accent_color = theme.accent_color(system_clock::ticks() as f32);
+    background::create(
+        theme.background.kind.for_round(round),
+        xmax,
+        ymax,
+        theme.background.base_color,
+        accent_color,
+    )
 }
 
 fn game<S: lcd::Framebuffer, T: lcd::Framebuffer>(
     renderer: &mut Renderer<S>,
     top_renderer: &mut Renderer<T>,
-    i2c_3: &mut i2c::I2C,
+    input: &mut Input,
     highscore: &mut i32,
+    round: i32,
+    theme_index: usize,
 ) {
-    renderer.clear();
-
-    let white_color = Color::from_hex(0xffffff);
+    let theme = Theme::builtin(theme_index);
+    info!("round {} with theme {}", round, theme.name);
 
     let xmax = renderer.get_width();
     let ymax = renderer.get_height();
 
-    let mut score = 0;
-    let mut last_tapped = false;
-    let mut last_ms = system_clock::ticks();
+    renderer.set_bg(get_background(xmax, ymax, round, &theme));
+    renderer.clear();
+
+    let previous_highscore = *highscore;
     let mut ms;
 
-    let base_x = xmax / 2;
-    let mut base_y = ymax;
+    let start_ms = system_clock::ticks();
+    let mut tower = Tower::new(xmax / 2, ymax, (start_ms % 360) as f32, start_ms);
 
-    let font = FontRenderer::new(TTF, 20.0);
-    let big_font = FontRenderer::new(TTF, 32.0);
+    let hud = HudLayout::new(xmax, ymax);
+    let font = FontRenderer::new(TTF, hud.font_size);
+    let big_font = FontRenderer::new(TTF, hud.big_font_size);
 
-    top_renderer.draw_text(&font, "Current Score", 0, 0, white_color);
-    top_renderer.draw_text(&font, "Highscore", xmax - 81, 0, white_color);
+    draw_hud_labels(top_renderer, &font, &theme, &hud);
 
     let mut redraw_score = true;
     let mut redraw_highscore = true;
+    let mut shown_missed_vsyncs = None;
+    let mut shown_touch_available = None;
 
-    let mut hue = (last_ms % 360) as f32;
+    let mut profiler = Profiler::new(PROFILER_WINDOW);
+    let mut show_profiler = false;
+    let mut last_touch_count = 0;
+    let mut pending_touch_ms = None;
+    let small_font = FontRenderer::new(TTF, hud.font_size * 0.6);
+    let profiler_row_height = hud.line_height * 3 / 5;
+    let profiler_height = profiler_row_height * profiler::SECTION_COUNT as i32;
+    let profiler_y = ymax - hud.line_height - profiler_height;
 
-    let block_height = 15;
-    let mut blocks = Vec::new();
-    let mut current_block = Block::new(-50, -60, -50, 100, 60, 100, 0f32);
-    draw_block(renderer, &current_block, base_x, base_y, hue);
-    blocks.push(current_block);
-    current_block = Block::new(-50, -60 - block_height, -50, 100, block_height, 100, 0f32);
+    draw_tower(renderer, &tower, ymax, &theme);
 
     loop {
         ms = system_clock::ticks();
 
-        let mut size = current_block.width;
-        if current_block.depth > size {
-            size = current_block.depth;
-        }
-        let p_time = 30 * size + 500;
-        let mut p = ((ms - last_ms) as i32 % p_time) as f32 / p_time as f32 * 2f32;
-        if p > 1f32 {
-            p = 2f32 - p;
+        profiler.begin(Section::Update);
+        tower.move_current(ms);
+        profiler.end(Section::Update);
+
+        profiler.begin(Section::BeginFrame);
+        top_renderer.begin_frame();
+        profiler.end(Section::BeginFrame);
+
+        profiler.begin(Section::Draw);
+        tower.current_block.draw(top_renderer, tower.base_x, tower.base_y, theme.hud_color);
+        profiler.end(Section::Draw);
+
+        profiler.begin(Section::EndFrame);
+        top_renderer.end_frame();
+        profiler.end(Section::EndFrame);
+
+        profiler.begin(Section::Input);
+        let taps = input.poll();
+        let touch_count = input.touches().len();
+        profiler.end(Section::Input);
+
+        profiler.begin(Section::Update);
+        let two_finger_tapped = touch_count >= 2 && last_touch_count < 2;
+        let three_finger_tapped = touch_count >= 3 && last_touch_count < 3;
+        last_touch_count = touch_count;
+        // the buttons cut at once, a touch is held back until it is clear
+        // that it is not the first finger of a two finger tap
+        let button_tap_ms = taps.iter().filter(|tap| tap.source != Source::Touch).map(|tap| tap.ms).min();
+        if touch_count >= 2 {
+            pending_touch_ms = None;
+        } else if pending_touch_ms.is_none() {
+            pending_touch_ms = taps.iter().filter(|tap| tap.source == Source::Touch).map(|tap| tap.ms).min();
         }
-        p = -2f32 * p * p * p + 3f32 * p * p;
-
-        {
-            let last_block = &blocks.last().unwrap();
-            if score % 2 == 0 {
-                current_block.x = ((3f32 * current_block.width as f32 * (p - 0.5f32)) as i32
-                    - current_block.width / 2 + last_block.x
-                    + last_block.width / 2) / 2 * 2;
-            } else {
-                current_block.z = ((3f32 * current_block.depth as f32 * (p - 0.5f32)) as i32
-                    - current_block.depth / 2 + last_block.z
-                    + last_block.depth / 2) / 2 * 2;
+        let touch_tap_ms = match pending_touch_ms {
+            Some(tap_ms) if tap_ms + TWO_FINGER_DELAY_MS <= ms => {
+                pending_touch_ms = None;
+                Some(tap_ms)
             }
-            top_renderer.begin_frame();
-            current_block.draw(top_renderer, base_x, base_y, white_color);
-            top_renderer.end_frame();
-        }
-
-        let tapped = !&touch::touches(i2c_3).unwrap().is_empty();
-        if tapped && !last_tapped {
-            {
-                let last_block = &blocks.last().unwrap();
-                let fit_distance = 3;
-
-                if abs(current_block.x - last_block.x) <= fit_distance
-                    && abs(current_block.z - last_block.z) <= fit_distance
-                {
-                    current_block.x = last_block.x;
-                    current_block.z = last_block.z;
-
-                    let in_a_row = 5;
-                    if blocks.len() >= in_a_row {
-                        let mut is_perfect = true;
-                        for i in blocks.len() - in_a_row + 1..blocks.len() {
-                            let lower_block = &blocks[i - 1];
-                            let b = &blocks[i];
-                            if b.x != lower_block.x || b.z != lower_block.z
-                                || b.width != lower_block.width
-                                || b.depth != lower_block.depth
-                            {
-                                is_perfect = false;
-                                break;
-                            }
-                        }
-                        if is_perfect {
-                            let bonus = 4;
-                            current_block.x -= bonus;
-                            current_block.z -= bonus;
-                            current_block.width += 2 * bonus;
-                            current_block.depth += 2 * bonus;
-                        }
-                    }
-                } else {
-                    if current_block.x < last_block.x {
-                        current_block.width -= last_block.x - current_block.x;
-                        current_block.x = last_block.x;
-                    }
-                    if current_block.x + current_block.width > last_block.x + last_block.width {
-                        current_block.width -=
-                            current_block.x + current_block.width - last_block.x - last_block.width;
-                    }
-                    if current_block.z < last_block.z {
-                        current_block.depth -= last_block.z - current_block.z;
-                        current_block.z = last_block.z;
-                    }
-                    if current_block.z + current_block.depth > last_block.z + last_block.depth {
-                        current_block.depth -=
-                            current_block.z + current_block.depth - last_block.z - last_block.depth;
-                    }
-                }
+            _ => None,
+        };
+        let tap_ms = button_tap_ms.into_iter().chain(touch_tap_ms).min();
+        if three_finger_tapped && SCREENSHOT_GESTURE {
+            screenshot::take_screenshot();
+        } else if two_finger_tapped {
+            show_profiler = !show_profiler;
+            if !show_profiler {
+                top_renderer.clear_area(0, profiler_y, xmax, profiler_height);
             }
-
-            if current_block.width < 4 || current_block.depth < 4 {
+        } else if let Some(tap_ms) = tap_ms {
+            // cut where the block was at the moment of the tap, not where it
+            // was drawn in this frame
+            tower.move_current(tap_ms);
+            if !tower.cut() {
+                info!("game over with score {}", tower.score);
                 let text = "Game Over";
                 let mut score_text = String::from("Your score is ");
-                score_text.push_str(&score.to_string());
+                score_text.push_str(&tower.score.to_string());
 
                 top_renderer.set_immediate(true);
                 top_renderer.begin_frame();
                 top_renderer.draw_text(
                     &big_font,
                     text,
-                    (xmax - text.len() as i32 * 14) / 2,
-                    ymax / 2 - 32,
-                    white_color,
+                    (xmax - hud.big_text_width(text)) / 2,
+                    ymax / 2 - hud.big_line_height,
+                    theme.hud_color,
                 );
                 top_renderer.draw_text(
                     &font,
                     &score_text,
-                    (xmax - score_text.len() as i32 * 9) / 2,
+                    (xmax - hud.text_width(&score_text)) / 2,
                     ymax / 2,
-                    white_color,
+                    theme.hud_color,
                 );
+                if tower.score > previous_highscore {
+                    info!("new highscore {}, was {}", tower.score, previous_highscore);
+                    let medal = &assets::MEDAL;
+                    top_renderer.draw_image(
+                        medal,
+                        (xmax - medal.width) / 2,
+                        ymax / 2 - hud.big_line_height - hud.line_height / 2 - medal.height,
+                    );
+                }
                 top_renderer.end_frame();
                 top_renderer.set_immediate(false);
                 return;
             }
 
-            if base_y + current_block.y < ymax / 3 {
-                let min_x = blocks.first().unwrap().min_x(base_x, base_y) - 5;
-                let min_y = blocks.last().unwrap().min_y(base_x, base_y) - 5;
-                let max_x = blocks.first().unwrap().max_x(base_x, base_y) + 5;
-                renderer.clear_area(min_x, min_y, max_x - min_x + 1, ymax - min_y);
-                base_y += ymax / 3;
-                for b in blocks.iter() {
-                    if b.min_y(base_x, base_y) < ymax {
-                        draw_block(renderer, b, base_x, base_y, b.hue);
-                    }
-                }
+            if tower.follow(ymax) {
+                // the background scrolls along with the camera, so it has to be redrawn completely
+                renderer.set_bg_scroll(tower.base_y - ymax);
+                renderer.clear();
+                draw_tower(renderer, &tower, ymax, &theme);
             }
 
-            hue = ((hue as i32 + 10) % 360) as f32;
-            draw_block(renderer, &current_block, base_x, base_y, hue);
-            blocks.push(current_block);
-            let last_block = &blocks.last().unwrap();
-            current_block = Block::new(
-                last_block.x,
-                last_block.y - block_height,
-                last_block.z,
-                last_block.width,
-                block_height,
-                last_block.depth,
-                hue,
-            );
-
-            last_ms = ms - (ms as i32 % 100) as usize;
+            profiler.end(Section::Update);
+            profiler.begin(Section::Draw);
+            place_block(renderer, &mut tower, tap_ms, &theme);
+            profiler.end(Section::Draw);
+            profiler.begin(Section::Update);
 
-            score += 1;
-            if score > *highscore {
-                *highscore = score;
+            if tower.score > *highscore {
+                *highscore = tower.score;
                 redraw_highscore = true;
             }
 
             redraw_score = true;
         }
-        last_tapped = tapped;
+        profiler.end(Section::Update);
 
+        profiler.begin(Section::Draw);
         if redraw_score {
-            top_renderer.clear_area(0, 20, 40, 20);
-            top_renderer.draw_text(&font, &score.to_string(), 0, 20, white_color);
+            top_renderer.clear_area(0, hud.line_height, xmax / 2, hud.line_height);
+            top_renderer.draw_text(&font, &tower.score.to_string(), 0, hud.line_height, theme.hud_color);
             redraw_score = false;
         }
         if redraw_highscore {
-            top_renderer.clear_area(xmax - 40, 20, 40, 20);
+            top_renderer.clear_area(xmax / 2, hud.line_height, xmax - xmax / 2, hud.line_height);
             let text = (*highscore).to_string();
             top_renderer.draw_text(
                 &font,
                 &text,
-                xmax - 9 * text.chars().count() as i32,
-                20,
-                white_color,
+                xmax - hud.text_width(&text),
+                hud.line_height,
+                theme.hud_color,
             );
             redraw_highscore = false;
         }
+        if DEBUG_OVERLAY && shown_missed_vsyncs != Some(vsync::missed_vsyncs()) {
+            let missed = vsync::missed_vsyncs();
+            let mut text = String::from("missed vsyncs ");
+            text.push_str(&missed.to_string());
+            top_renderer.clear_area(0, ymax - hud.line_height, xmax, hud.line_height);
+            top_renderer.draw_text(&font, &text, 0, ymax - hud.line_height, theme.hud_color);
+            shown_missed_vsyncs = Some(missed);
+        }
+        if shown_touch_available != Some(input.is_touch_available()) {
+            draw_touch_notice(top_renderer, &font, &theme, &hud, input.is_touch_available());
+            shown_touch_available = Some(input.is_touch_available());
+        }
+        profiler.end(Section::Draw);
+
+        let restore_cycles = renderer.take_restore_cycles().wrapping_add(top_renderer.take_restore_cycles());
+        profiler.add(Section::Restore, restore_cycles);
+        profiler.end_frame();
+        if profiler.take_updated() && show_profiler {
+            top_renderer.clear_area(0, profiler_y, xmax, profiler_height);
+            profiler.draw(
+                top_renderer,
+                &small_font,
+                0,
+                profiler_y,
+                xmax,
+                profiler_row_height,
+                profiler::CPU_FREQUENCY / FPS as u32,
+                theme.hud_color,
+            );
+        }
+
+        wait_for_next_frame(ms);
+    }
+}
+
+/// Two players stack side by side in landscape orientation, each one taps on
+/// their half of the screen. The first tower that fails loses.
+fn versus<S: lcd::Framebuffer, T: lcd::Framebuffer>(
+    renderer: &mut Renderer<S>,
+    top_renderer: &mut Renderer<T>,
+    input: &mut Input,
+    round: i32,
+    theme_index: usize,
+) {
+    let theme = Theme::builtin(theme_index);
+
+    let xmax = renderer.get_width();
+    let ymax = renderer.get_height();
+    let half = xmax / 2;
+    // the divider line at x = half is not part of either view
+    let views = [Rect::new(0, 0, half, ymax), Rect::new(half + 1, 0, xmax - half - 1, ymax)];
+
+    renderer.set_bg(get_background(xmax, ymax, round, &theme));
+    renderer.clear();
+    top_renderer.clear();
+
+    let start_ms = system_clock::ticks();
+    let hue = (start_ms % 360) as f32;
+    let mut towers = [
+        Tower::new(half / 2, ymax, hue, start_ms),
+        Tower::new(half + half / 2, ymax, hue, start_ms),
+    ];
+
+    let hud = HudLayout::new(xmax, ymax);
+    let font = FontRenderer::new(TTF, hud.font_size);
+    let big_font = FontRenderer::new(TTF, hud.big_font_size);
+
+    top_renderer.draw_line(half, hud.height(), half, ymax, theme.hud_color);
+    for (i, view) in views.iter().enumerate() {
+        renderer.push_clip(*view);
+        draw_tower(renderer, &towers[i], ymax, &theme);
+        renderer.pop_clip();
+
+        let mut label = String::from("Player ");
+        label.push_str(&(i + 1).to_string());
+        top_renderer.draw_text(&font, &label, view.x + hud.margin, 0, theme.hud_color);
+    }
+
+    let mut redraw_score = [true; 2];
+    let mut shown_touch_available = None;
+
+    loop {
+        let ms = system_clock::ticks();
+
+        top_renderer.begin_frame();
+        for (i, view) in views.iter().enumerate() {
+            let tower = &mut towers[i];
+            tower.move_current(ms);
+            top_renderer.push_clip(*view);
+            tower.current_block.draw(top_renderer, tower.base_x, tower.base_y, theme.hud_color);
+            top_renderer.pop_clip();
+        }
+        top_renderer.end_frame();
+
+        // the user button belongs to the first player, the external button
+        // to the second one
+        let mut tapped = [None; 2];
+        for tap in input.poll().iter() {
+            let player = match (tap.source, tap.position) {
+                (Source::Touch, Some(t)) => {
+                    if renderer.touch_position(t.x, t.y).0 < half {
+                        0
+                    } else {
+                        1
+                    }
+                }
+                (Source::ExternalButton, _) => 1,
+                _ => 0,
+            };
+            if tapped[player].map_or(true, |ms| tap.ms < ms) {
+                tapped[player] = Some(tap.ms);
+            }
+        }
+
+        for (i, view) in views.iter().enumerate() {
+            let tap_ms = match tapped[i] {
+                Some(tap_ms) => tap_ms,
+                None => continue,
+            };
+            let tower = &mut towers[i];
+            tower.move_current(tap_ms);
+            renderer.push_clip(*view);
+            if !tower.cut() {
+                renderer.pop_clip();
+                let winner = 2 - i;
+                info!("player {} wins, scores {} and {}", winner, towers[0].score, towers[1].score);
+                let mut text = String::from("Player ");
+                text.push_str(&winner.to_string());
+                text.push_str(" wins");
+
+                top_renderer.set_immediate(true);
+                top_renderer.begin_frame();
+                top_renderer.draw_text(
+                    &big_font,
+                    &text,
+                    (xmax - hud.big_text_width(&text)) / 2,
+                    ymax / 2 - hud.big_line_height,
+                    theme.hud_color,
+                );
+                top_renderer.end_frame();
+                top_renderer.set_immediate(false);
+                return;
+            }
+
+            // the background does not scroll, only this half is redrawn
+            if tower.follow(ymax) {
+                renderer.clear_area(view.x, view.y, view.width, view.height);
+                draw_tower(renderer, tower, ymax, &theme);
+            }
+            place_block(renderer, tower, tap_ms, &theme);
+            renderer.pop_clip();
+            redraw_score[i] = true;
+        }
+
+        for (i, view) in views.iter().enumerate() {
+            if redraw_score[i] {
+                let x = view.x + hud.margin;
+                top_renderer.clear_area(x, hud.line_height, view.width - hud.margin, hud.line_height);
+                top_renderer.draw_text(&font, &towers[i].score.to_string(), x, hud.line_height, theme.hud_color);
+                redraw_score[i] = false;
+            }
+        }
+        if shown_touch_available != Some(input.is_touch_available()) {
+            draw_touch_notice(top_renderer, &font, &theme, &hud, input.is_touch_available());
+            shown_touch_available = Some(input.is_touch_available());
+        }
+
+        wait_for_next_frame(ms);
+    }
+}
+
+/// Waits between two rounds. A tap on the theme line at the bottom of the
+/// screen switches to the next theme, any other tap or a button starts the
+/// next round with the chosen theme.
+fn choose_theme<T: lcd::Framebuffer>(
+    top_renderer: &mut Renderer<T>,
+    input: &mut Input,
+    theme_index: usize,
+) -> usize {
+    let xmax = top_renderer.get_width();
+    let ymax = top_renderer.get_height();
+    let hud = HudLayout::new(xmax, ymax);
+    let font = FontRenderer::new(TTF, hud.font_size);
+    let y = ymax - 2 * hud.line_height;
+
+    let mut theme_index = theme_index;
+    let mut redraw = true;
+    top_renderer.set_immediate(true);
+    loop {
+        let ms = system_clock::ticks();
+        if redraw {
+            let theme = Theme::builtin(theme_index);
+            let mut text = String::from("theme ");
+            text.push_str(theme.name);
+            text.push_str(", tap to change");
+            top_renderer.begin_frame();
+            top_renderer.clear_area(0, y, xmax, hud.line_height);
+            top_renderer.draw_text(&font, &text, (xmax - hud.text_width(&text)) / 2, y, theme.hud_color);
+            top_renderer.end_frame();
+            redraw = false;
+        }
+
+        let taps = input.poll();
+        let theme_tapped = taps.iter().any(|tap| match tap.position {
+            Some(t) => top_renderer.touch_position(t.x, t.y).1 >= y,
+            None => false,
+        });
+        if theme_tapped {
+            theme_index = (theme_index + 1) % THEME_COUNT;
+            info!("theme {}", Theme::builtin(theme_index).name);
+            redraw = true;
+        } else if !taps.is_empty() {
+            break;
+        }
+
+        wait_for_next_frame(ms);
+    }
+    top_renderer.set_immediate(false);
+    top_renderer.clear();
+    theme_index
+}
+
+/// Draws the visible blocks of a tower into the background layer.
+fn draw_tower<T: lcd::Framebuffer>(renderer: &mut Renderer<T>, tower: &Tower, ymax: i32, theme: &Theme) {
+    for (layer, b) in tower.blocks.iter().enumerate() {
+        if b.min_y(tower.base_x, tower.base_y) < ymax {
+            draw_block(renderer, b, tower.base_x, tower.base_y, b.hue, layer, theme);
+        }
+    }
+}
+
+/// Draws the current block of a tower in the next hue and stacks it.
+fn place_block<T: lcd::Framebuffer>(renderer: &mut Renderer<T>, tower: &mut Tower, ms: usize, theme: &Theme) {
+    let hue = theme.next_hue(tower.hue);
+    tower.current_block.hue = hue;
+    let layer = tower.blocks.len();
+    info!(
+        "placed block {} with {}x{} at {}, {}",
+        layer,
+        tower.current_block.width,
+        tower.current_block.depth,
+        tower.current_block.x,
+        tower.current_block.z
+    );
+    draw_block(renderer, &tower.current_block, tower.base_x, tower.base_y, hue, layer, theme);
+    tower.stack(ms, hue);
+}
 
-        // Timer
+fn handle_exti15_10() {
+    input::handle_interrupt();
+    touch_interrupt::handle_exti();
+}
+
+/// Tells the player to use the user button while the touch controller does
+/// not respond, in the line above the bottom of the screen.
+fn draw_touch_notice<T: lcd::Framebuffer>(
+    top_renderer: &mut Renderer<T>,
+    font: &FontRenderer,
+    theme: &Theme,
+    hud: &HudLayout,
+    touch_available: bool,
+) {
+    let xmax = top_renderer.get_width();
+    let y = top_renderer.get_height() - 2 * hud.line_height;
+    top_renderer.clear_area(0, y, xmax, hud.line_height);
+    if !touch_available {
+        let text = "touch unavailable, use the blue button";
+        top_renderer.draw_text(font, text, (xmax - hud.text_width(text)) / 2, y, theme.hud_color);
+    }
+}
+
+/// Shows a message on the crash screen until the screen was tapped.
+fn show_until_tap<T: lcd::Framebuffer>(
+    top_renderer: &mut Renderer<T>,
+    input: &mut Input,
+    title: &str,
+    lines: &[String],
+) {
+    crash::draw_screen(top_renderer, TTF, title, lines);
+    while input.poll().is_empty() {}
+    while input.is_pressed() {
+        input.poll();
+    }
+    top_renderer.clear();
+}
+
+// Timer, the frame rate is limited by end_frame if vsync is available
+fn wait_for_next_frame(ms: usize) {
+    watchdog::feed();
+    if !vsync::is_enabled() {
         let ms_per_frame = (1000 / FPS) as usize;
         loop {
             let cur_ms = system_clock::ticks();
@@ -399,38 +834,73 @@ fn game<S: lcd::Framebuffer, T: lcd::Framebuffer>(
     }
 }
 
-fn abs(value: i32) -> i32 {
-    if value < 0 {
-        -value
-    } else {
-        value
+/// Sizes of the HUD texts. The layout was made for the 272 pixels of the
+/// shorter side of the 4.3" panel and is scaled to other screens.
+struct HudLayout {
+    font_size: f32,
+    big_font_size: f32,
+    line_height: i32,
+    big_line_height: i32,
+    char_width: i32,
+    big_char_width: i32,
+    margin: i32,
+}
+
+impl HudLayout {
+    fn new(xmax: i32, ymax: i32) -> HudLayout {
+        let short_side = if xmax < ymax { xmax } else { ymax };
+        let scale = short_side as f32 / 272f32;
+        HudLayout {
+            font_size: 20f32 * scale,
+            big_font_size: 32f32 * scale,
+            line_height: (20f32 * scale) as i32,
+            big_line_height: (32f32 * scale) as i32,
+            // the advance of the monospace font
+            char_width: (9f32 * scale) as i32,
+            big_char_width: (14f32 * scale) as i32,
+            margin: (4f32 * scale) as i32,
+        }
+    }
+
+    /// Height of the label and score lines at the top of the screen.
+    fn height(&self) -> i32 {
+        2 * self.line_height
+    }
+
+    fn text_width(&self, text: &str) -> i32 {
+        self.char_width * text.chars().count() as i32
+    }
+
+    fn big_text_width(&self, text: &str) -> i32 {
+        self.big_char_width * text.chars().count() as i32
     }
 }
 
+fn draw_hud_labels<T: lcd::Framebuffer>(
+    top_renderer: &mut Renderer<T>,
+    font: &FontRenderer,
+    theme: &Theme,
+    hud: &HudLayout,
+) {
+    let xmax = top_renderer.get_width();
+    let highscore_label = "Highscore";
+    top_renderer.draw_text(font, "Current Score", 0, 0, theme.hud_color);
+    top_renderer.draw_text(font, highscore_label, xmax - hud.text_width(highscore_label), 0, theme.hud_color);
+}
+
 fn draw_block<T: lcd::Framebuffer>(
     renderer: &mut Renderer<T>,
     block: &Block,
     base_x: i32,
     base_y: i32,
     hue: f32,
+    layer: usize,
+    theme: &Theme,
 ) {
-    let base_color = hsv_color(hue, 0.5f32, 1f32);
-
-    let _outline_color = fix_color(weight_color(base_color, 1f32));
-    let left_color = fix_color(weight_color(base_color, 1f32));
-    let right_color = fix_color(weight_color(base_color, 0.6f32));
-    let top_color = fix_color(weight_color(base_color, 0.8f32));
+    let faces = theme.face_colors(hue, layer);
 
-    block.draw_solid(renderer, base_x, base_y, left_color, right_color, top_color);
-    //block.draw(renderer, base_x, base_y, outline_color);
-}
-
-fn fix_color(color: Color) -> Color {
-    Color::from_hex(swap_bits(color.to_rgb(), 1, 4))
-}
-
-fn swap_bits(value: u32, pos0: u8, pos1: u8) -> u32 {
-    let a = (value >> pos0) & 1;
-    let b = (value >> pos1) & 1;
-    value & !(1 << pos0) & !(1 << pos1) | (a << pos1) | (b << pos0)
+    block.draw_shaded(renderer, base_x, base_y, &faces);
+    if let Some(outline_color) = theme.outline_color {
+        block.draw(renderer, base_x, base_y, outline_color);
+    }
 }
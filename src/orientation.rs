@@ -0,0 +1,113 @@
+#![allow(dead_code)]
+
+/// How the screen coordinates used for drawing are mapped onto the panel.
+///
+/// The rotation is clockwise, so with `Portrait` the top of the screen is at
+/// the right edge of the panel. Mirrored variants flip the screen horizontally
+/// before it is rotated.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+    LandscapeFlipped,
+    PortraitFlipped,
+    LandscapeMirrored,
+    PortraitMirrored,
+    LandscapeFlippedMirrored,
+    PortraitFlippedMirrored,
+}
+
+impl Orientation {
+    /// Clockwise rotation in quarter turns.
+    pub fn rotation(&self) -> u8 {
+        match *self {
+            Orientation::Landscape | Orientation::LandscapeMirrored => 0,
+            Orientation::Portrait | Orientation::PortraitMirrored => 1,
+            Orientation::LandscapeFlipped | Orientation::LandscapeFlippedMirrored => 2,
+            Orientation::PortraitFlipped | Orientation::PortraitFlippedMirrored => 3,
+        }
+    }
+
+    pub fn is_mirrored(&self) -> bool {
+        match *self {
+            Orientation::LandscapeMirrored
+            | Orientation::PortraitMirrored
+            | Orientation::LandscapeFlippedMirrored
+            | Orientation::PortraitFlippedMirrored => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_portrait(&self) -> bool {
+        self.rotation() % 2 == 1
+    }
+
+    /// The same orientation turned upside down, e.g. for a board mounted the
+    /// other way round.
+    pub fn flipped(&self) -> Orientation {
+        Orientation::from_parts((self.rotation() + 2) % 4, self.is_mirrored())
+    }
+
+    pub fn from_parts(rotation: u8, mirrored: bool) -> Orientation {
+        match (rotation % 4, mirrored) {
+            (0, false) => Orientation::Landscape,
+            (1, false) => Orientation::Portrait,
+            (2, false) => Orientation::LandscapeFlipped,
+            (3, false) => Orientation::PortraitFlipped,
+            (0, true) => Orientation::LandscapeMirrored,
+            (1, true) => Orientation::PortraitMirrored,
+            (2, true) => Orientation::LandscapeFlippedMirrored,
+            _ => Orientation::PortraitFlippedMirrored,
+        }
+    }
+
+    /// Size of the screen for a panel of the given size.
+    pub fn screen_size(&self, panel_width: i32, panel_height: i32) -> (i32, i32) {
+        if self.is_portrait() {
+            (panel_height, panel_width)
+        } else {
+            (panel_width, panel_height)
+        }
+    }
+
+    /// Maps a screen pixel to the panel pixel it is shown on.
+    pub fn to_panel(&self, x: i32, y: i32, panel_width: i32, panel_height: i32) -> (i32, i32) {
+        let (screen_width, _) = self.screen_size(panel_width, panel_height);
+        let x = if self.is_mirrored() { screen_width - 1 - x } else { x };
+        match self.rotation() {
+            0 => (x, y),
+            1 => (panel_width - 1 - y, x),
+            2 => (panel_width - 1 - x, panel_height - 1 - y),
+            _ => (y, panel_height - 1 - x),
+        }
+    }
+
+    /// Maps a panel pixel, e.g. a touch position, to screen coordinates.
+    pub fn from_panel(&self, x: i32, y: i32, panel_width: i32, panel_height: i32) -> (i32, i32) {
+        let (screen_width, _) = self.screen_size(panel_width, panel_height);
+        let (sx, sy) = match self.rotation() {
+            0 => (x, y),
+            1 => (y, panel_width - 1 - x),
+            2 => (panel_width - 1 - x, panel_height - 1 - y),
+            _ => (panel_height - 1 - y, x),
+        };
+        if self.is_mirrored() {
+            (screen_width - 1 - sx, sy)
+        } else {
+            (sx, sy)
+        }
+    }
+
+    /// Maps a screen rectangle to the panel rectangle covering the same pixels.
+    pub fn rect_to_panel(&self, x: i32, y: i32, w: i32, h: i32, panel_width: i32, panel_height: i32) -> (i32, i32, i32, i32) {
+        if w <= 0 || h <= 0 {
+            let (px, py) = self.to_panel(x, y, panel_width, panel_height);
+            return (px, py, 0, 0);
+        }
+        let (ax, ay) = self.to_panel(x, y, panel_width, panel_height);
+        let (bx, by) = self.to_panel(x + w - 1, y + h - 1, panel_width, panel_height);
+        let (x0, x1) = if ax < bx { (ax, bx) } else { (bx, ax) };
+        let (y0, y1) = if ay < by { (ay, by) } else { (by, ay) };
+        (x0, y0, x1 - x0 + 1, y1 - y0 + 1)
+    }
+}
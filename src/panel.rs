@@ -0,0 +1,33 @@
+#![allow(dead_code)]
+
+use core::ptr;
+
+// The LCD driver owns the LTDC peripheral, the timing it was configured with
+// is read back from the registers.
+const LTDC_BASE: usize = 0x4001_6800;
+const LTDC_BPCR: usize = LTDC_BASE + 0x0C;
+const LTDC_AWCR: usize = LTDC_BASE + 0x10;
+
+/// The size of the active area of the panel, in pixels. Only valid after
+/// `lcd::init`.
+pub fn size() -> (i32, i32) {
+    // both registers hold accumulated widths in the upper and accumulated
+    // heights in the lower half, the active area starts after the back porch
+    let (back_porch, active) = unsafe {
+        (
+            ptr::read_volatile(LTDC_BPCR as *const u32),
+            ptr::read_volatile(LTDC_AWCR as *const u32),
+        )
+    };
+    let width = (active >> 16 & 0xFFF) - (back_porch >> 16 & 0xFFF);
+    let height = (active & 0x7FF) - (back_porch & 0x7FF);
+    (width as i32, height as i32)
+}
+
+/// The first line after the active area, counted from the start of the vertical
+/// sync like the LTDC line counter. Only valid after `lcd::init`.
+pub fn vblank_line() -> u32 {
+    // the accumulated active height is the last active line
+    let active = unsafe { ptr::read_volatile(LTDC_AWCR as *const u32) };
+    (active & 0x7FF) + 1
+}
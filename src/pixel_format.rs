@@ -0,0 +1,227 @@
+#![allow(dead_code)]
+
+use stm32f7::lcd::Color;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Argb8888,
+    Rgb565,
+    Argb4444,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match *self {
+            PixelFormat::Argb8888 => 4,
+            PixelFormat::Rgb565 | PixelFormat::Argb4444 => 2,
+        }
+    }
+
+    pub fn encode(&self, color: Color) -> u32 {
+        let a = color.alpha as u32;
+        let r = color.red as u32;
+        let g = color.green as u32;
+        let b = color.blue as u32;
+        match *self {
+            PixelFormat::Argb8888 => a << 24 | r << 16 | g << 8 | b,
+            PixelFormat::Rgb565 => (r >> 3) << 11 | (g >> 2) << 5 | b >> 3,
+            PixelFormat::Argb4444 => (a >> 4) << 12 | (r >> 4) << 8 | (g >> 4) << 4 | b >> 4,
+        }
+    }
+
+    pub fn decode(&self, value: u32) -> Color {
+        match *self {
+            PixelFormat::Argb8888 => Color::rgba(
+                (value >> 16) as u8,
+                (value >> 8) as u8,
+                value as u8,
+                (value >> 24) as u8,
+            ),
+            PixelFormat::Rgb565 => Color::rgb(
+                expand_bits(value >> 11, 5),
+                expand_bits(value >> 5, 6),
+                expand_bits(value, 5),
+            ),
+            PixelFormat::Argb4444 => Color::rgba(
+                expand_bits(value >> 8, 4),
+                expand_bits(value >> 4, 4),
+                expand_bits(value, 4),
+                expand_bits(value >> 12, 4),
+            ),
+        }
+    }
+}
+
+/// Scales the lowest `bits` bits of `value` to the full 8 bit range by
+/// replicating the high bits into the low bits.
+pub fn expand_bits(value: u32, bits: u32) -> u8 {
+    let v = value & ((1 << bits) - 1);
+    let mut result = v << (8 - bits);
+    let mut shift = bits;
+    while shift < 8 {
+        result |= result >> shift;
+        shift *= 2;
+    }
+    result as u8
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+}
+
+/// Swaps two bits of one 8 bit color channel, e.g. to compensate for data
+/// lines that are wired in a different order than the LTDC expects.
+#[derive(Clone, Copy)]
+pub struct BitSwap {
+    pub channel: Channel,
+    pub bit0: u8,
+    pub bit1: u8,
+}
+
+impl BitSwap {
+    pub fn apply(&self, color: Color) -> Color {
+        let mut color = color;
+        match self.channel {
+            Channel::Red => color.red = swap_bits(color.red, self.bit0, self.bit1),
+            Channel::Green => color.green = swap_bits(color.green, self.bit0, self.bit1),
+            Channel::Blue => color.blue = swap_bits(color.blue, self.bit0, self.bit1),
+        }
+        color
+    }
+}
+
+fn swap_bits(value: u8, pos0: u8, pos1: u8) -> u8 {
+    let a = (value >> pos0) & 1;
+    let b = (value >> pos1) & 1;
+    value & !(1 << pos0) & !(1 << pos1) | (a << pos1) | (b << pos0)
+}
+
+// The blue data lines 1 and 4 of the STM32F746G Discovery panel are swapped.
+static DISCOVERY_CORRECTIONS: [BitSwap; 1] = [BitSwap {
+    channel: Channel::Blue,
+    bit0: 1,
+    bit1: 4,
+}];
+
+/// Pixel format of a layer together with the corrections the board needs
+/// so that a color ends up on the panel the way it was meant.
+#[derive(Clone, Copy)]
+pub struct PanelFormat {
+    pub format: PixelFormat,
+    pub corrections: &'static [BitSwap],
+}
+
+impl PanelFormat {
+    pub fn plain(format: PixelFormat) -> PanelFormat {
+        PanelFormat {
+            format: format,
+            corrections: &[],
+        }
+    }
+
+    pub fn discovery(format: PixelFormat) -> PanelFormat {
+        PanelFormat {
+            format: format,
+            corrections: &DISCOVERY_CORRECTIONS,
+        }
+    }
+
+    /// Converts a logical color into the color that has to be written to the framebuffer.
+    pub fn to_panel(&self, color: Color) -> Color {
+        let mut color = color;
+        for correction in self.corrections.iter() {
+            color = correction.apply(color);
+        }
+        color
+    }
+
+    /// Reverts `to_panel`, e.g. for colors read back from the framebuffer.
+    pub fn from_panel(&self, color: Color) -> Color {
+        let mut color = color;
+        for correction in self.corrections.iter().rev() {
+            color = correction.apply(color);
+        }
+        color
+    }
+
+    pub fn encode(&self, color: Color) -> u32 {
+        self.format.encode(self.to_panel(color))
+    }
+
+    pub fn decode(&self, value: u32) -> Color {
+        self.from_panel(self.format.decode(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FORMATS: [PixelFormat; 3] = [PixelFormat::Argb8888, PixelFormat::Rgb565, PixelFormat::Argb4444];
+
+    fn panel_formats(format: PixelFormat) -> [PanelFormat; 2] {
+        [PanelFormat::plain(format), PanelFormat::discovery(format)]
+    }
+
+    fn assert_same_color(a: Color, b: Color) {
+        assert_eq!(
+            (a.red, a.green, a.blue, a.alpha),
+            (b.red, b.green, b.blue, b.alpha)
+        );
+    }
+
+    #[test]
+    fn argb8888_colors_round_trip() {
+        for &panel in panel_formats(PixelFormat::Argb8888).iter() {
+            for v in 0..256u32 {
+                let v = v as u8;
+                let color = Color::rgba(v, 255 - v, v ^ 0x5A, v.rotate_left(3));
+                assert_same_color(panel.decode(panel.encode(color)), color);
+            }
+        }
+    }
+
+    #[test]
+    fn all_16_bit_values_round_trip() {
+        for &format in FORMATS.iter().filter(|f| f.bytes_per_pixel() == 2) {
+            for &panel in panel_formats(format).iter() {
+                for value in 0..0x1_0000u32 {
+                    let color = panel.decode(value);
+                    assert_eq!(panel.encode(color), value);
+                    assert_same_color(panel.decode(panel.encode(color)), color);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decoded_argb8888_values_round_trip() {
+        for &panel in panel_formats(PixelFormat::Argb8888).iter() {
+            for i in 0..0x1_0000u32 {
+                // spread the samples over all four channels
+                let value = i.wrapping_mul(0x9E37_79B9);
+                assert_eq!(panel.encode(panel.decode(value)), value);
+            }
+        }
+    }
+
+    #[test]
+    fn discovery_swaps_blue_bits_1_and_4() {
+        let panel = PanelFormat::discovery(PixelFormat::Argb8888);
+        let color = Color::rgba(0, 0, 1 << 1, 255);
+        assert_eq!(panel.encode(color), 0xFF00_0000 | 1 << 4);
+        assert_same_color(panel.decode(0xFF00_0000 | 1 << 4), color);
+    }
+
+    #[test]
+    fn expanded_bits_cover_the_full_range() {
+        assert_eq!(expand_bits(0, 5), 0);
+        assert_eq!(expand_bits(0x1F, 5), 255);
+        assert_eq!(expand_bits(0x3F, 6), 255);
+        assert_eq!(expand_bits(0xF, 4), 255);
+        assert_eq!(expand_bits(0x8, 4), 0x88);
+    }
+}
@@ -0,0 +1,222 @@
+#![allow(dead_code)]
+
+extern crate stm32f7_discovery as stm32f7;
+
+use core::ptr;
+
+use stm32f7::lcd::{Color, Framebuffer};
+use stm32f7::lcd::font::FontRenderer;
+
+use renderer::Renderer;
+
+const DEMCR: usize = 0xE000_EDFC;
+const DWT_CTRL: usize = 0xE000_1000;
+const DWT_CYCCNT: usize = 0xE000_1004;
+// the DWT of the Cortex-M7 is locked after reset
+const DWT_LAR: usize = 0xE000_1FB0;
+const DWT_UNLOCK: u32 = 0xC5AC_CE55;
+
+const DEMCR_TRCENA: u32 = 1 << 24;
+const CTRL_CYCCNTENA: u32 = 1 << 0;
+
+// the core clock set up by `system_clock::init`
+pub const CPU_FREQUENCY: u32 = 216_000_000;
+
+pub const SECTION_COUNT: usize = 6;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Input,
+    Update,
+    BeginFrame,
+    Draw,
+    EndFrame,
+    /// Restoring the background, this is part of `EndFrame` and `Draw`
+    Restore,
+}
+
+pub const SECTIONS: [Section; SECTION_COUNT] = [
+    Section::Input,
+    Section::Update,
+    Section::BeginFrame,
+    Section::Draw,
+    Section::EndFrame,
+    Section::Restore,
+];
+
+impl Section {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Section::Input => "input",
+            Section::Update => "update",
+            Section::BeginFrame => "begin",
+            Section::Draw => "draw",
+            Section::EndFrame => "end",
+            Section::Restore => "restore",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Starts the DWT cycle counter.
+pub fn init() {
+    unsafe {
+        let demcr = ptr::read_volatile(DEMCR as *const u32);
+        ptr::write_volatile(DEMCR as *mut u32, demcr | DEMCR_TRCENA);
+        ptr::write_volatile(DWT_LAR as *mut u32, DWT_UNLOCK);
+        ptr::write_volatile(DWT_CYCCNT as *mut u32, 0);
+        let ctrl = ptr::read_volatile(DWT_CTRL as *const u32);
+        ptr::write_volatile(DWT_CTRL as *mut u32, ctrl | CTRL_CYCCNTENA);
+    }
+}
+
+/// Core clock cycles since `init`, wrapping around after about 20 seconds.
+pub fn cycles() -> u32 {
+    unsafe { ptr::read_volatile(DWT_CYCCNT as *const u32) }
+}
+
+/// Cycles spent in a section per frame over the last window.
+#[derive(Clone, Copy, Default)]
+pub struct Summary {
+    pub min: u32,
+    pub avg: u32,
+    pub max: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Statistics {
+    min: u32,
+    max: u32,
+    total: u64,
+}
+
+impl Statistics {
+    fn new() -> Statistics {
+        Statistics {
+            min: u32::max_value(),
+            max: 0,
+            total: 0,
+        }
+    }
+
+    fn add(&mut self, value: u32) {
+        if value < self.min {
+            self.min = value;
+        }
+        if value > self.max {
+            self.max = value;
+        }
+        self.total += value as u64;
+    }
+}
+
+/// Times the sections of a frame and aggregates them over a window of frames.
+pub struct Profiler {
+    window: u32,
+    frames: u32,
+    started: [u32; SECTION_COUNT],
+    frame: [u32; SECTION_COUNT],
+    statistics: [Statistics; SECTION_COUNT],
+    summaries: [Summary; SECTION_COUNT],
+    updated: bool,
+}
+
+impl Profiler {
+    /// Creates a profiler that publishes a summary every `window` frames.
+    pub fn new(window: u32) -> Profiler {
+        init();
+        Profiler {
+            window: window,
+            frames: 0,
+            started: [0; SECTION_COUNT],
+            frame: [0; SECTION_COUNT],
+            statistics: [Statistics::new(); SECTION_COUNT],
+            summaries: [Summary::default(); SECTION_COUNT],
+            updated: false,
+        }
+    }
+
+    pub fn begin(&mut self, section: Section) {
+        self.started[section.index()] = cycles();
+    }
+
+    pub fn end(&mut self, section: Section) {
+        let elapsed = cycles().wrapping_sub(self.started[section.index()]);
+        self.add(section, elapsed);
+    }
+
+    /// Adds cycles that were measured somewhere else, e.g. by the renderer.
+    pub fn add(&mut self, section: Section, cycles: u32) {
+        let frame = &mut self.frame[section.index()];
+        *frame = frame.wrapping_add(cycles);
+    }
+
+    pub fn measure<F: FnOnce() -> R, R>(&mut self, section: Section, f: F) -> R {
+        self.begin(section);
+        let result = f();
+        self.end(section);
+        result
+    }
+
+    /// Finishes the current frame. Every `window` frames the summaries are updated.
+    pub fn end_frame(&mut self) {
+        for i in 0..SECTION_COUNT {
+            self.statistics[i].add(self.frame[i]);
+            self.frame[i] = 0;
+        }
+        self.frames += 1;
+        if self.frames < self.window {
+            return;
+        }
+
+        for i in 0..SECTION_COUNT {
+            let statistics = self.statistics[i];
+            self.summaries[i] = Summary {
+                min: statistics.min,
+                avg: (statistics.total / self.frames as u64) as u32,
+                max: statistics.max,
+            };
+            self.statistics[i] = Statistics::new();
+        }
+        self.frames = 0;
+        self.updated = true;
+    }
+
+    pub fn summary(&self, section: Section) -> Summary {
+        self.summaries[section.index()]
+    }
+
+    /// Returns true once after new summaries were published.
+    pub fn take_updated(&mut self) -> bool {
+        let updated = self.updated;
+        self.updated = false;
+        updated
+    }
+
+    /// Draws one bar per section. The bar shows the average, the marks the
+    /// minimum and the maximum, both relative to the frame time `budget`.
+    pub fn draw<T: Framebuffer>(&self, renderer: &mut Renderer<T>, font: &FontRenderer, x: i32, y: i32, width: i32, row_height: i32, budget: u32, color: Color) {
+        let label_width = width / 4;
+        let bar_width = width - label_width;
+        let background = Color::rgba(0, 0, 0, 160);
+        renderer.draw_rect_solid(x, y, width, row_height * SECTION_COUNT as i32, background);
+
+        for (row, section) in SECTIONS.iter().enumerate() {
+            let top = y + row as i32 * row_height;
+            let summary = self.summary(*section);
+            renderer.draw_text(font, section.name(), x, top, color);
+
+            let scale = |value: u32| {
+                let value = if value > budget { budget } else { value };
+                (value as u64 * bar_width as u64 / budget as u64) as i32
+            };
+            let bar_x = x + label_width;
+            renderer.draw_rect_solid(bar_x, top + 2, scale(summary.avg), row_height - 4, color);
+            renderer.draw_line(bar_x + scale(summary.min), top, bar_x + scale(summary.min), top + row_height - 1, color);
+            renderer.draw_line(bar_x + scale(summary.max), top, bar_x + scale(summary.max), top + row_height - 1, color);
+        }
+    }
+}
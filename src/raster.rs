@@ -0,0 +1,212 @@
+#![allow(dead_code)]
+
+// Scanline rasterization of polygons on a sub-pixel grid. Does not touch the
+// hardware, so it is also built by the host tests in `host_tests`.
+
+/// Number of sub-pixel steps per pixel used by the polygon rasterizer
+pub const SUBPIXEL: i32 = 16;
+
+pub const MAX_POLYGON_POINTS: usize = 8;
+const MAX_CROSSINGS: usize = 16;
+
+/// The top, left and right face of a block drawn by `Renderer::draw_block_3d_shaded`.
+/// The side faces share their edges with the top face and each other.
+pub fn block_faces(x: i32, y: i32, width: i32, height: i32, depth: i32) -> [[(i32, i32); 4]; 3] {
+    let back = (x + depth, y - depth / 2);
+    let left = (x, y);
+    let front = (x + width, y + width / 2);
+    let right = (x + width + depth, y + width / 2 - depth / 2);
+    [
+        [left, back, right, front],
+        [left, front, (front.0, front.1 + height), (left.0, left.1 + height)],
+        [front, right, (right.0, right.1 + height), (front.0, front.1 + height)],
+    ]
+}
+
+pub fn to_fixed_point(points: &[(i32, i32)]) -> ([(i32, i32); MAX_POLYGON_POINTS], usize) {
+    assert!(
+        points.len() <= MAX_POLYGON_POINTS,
+        "polygon with more than {} corners",
+        MAX_POLYGON_POINTS
+    );
+    let mut fixed = [(0, 0); MAX_POLYGON_POINTS];
+    let count = points.len();
+    for i in 0..count {
+        fixed[i] = (points[i].0 * SUBPIXEL, points[i].1 * SUBPIXEL);
+    }
+    (fixed, count)
+}
+
+pub fn div_floor(a: i32, b: i32) -> i32 {
+    if a >= 0 {
+        a / b
+    } else {
+        -((-a + b - 1) / b)
+    }
+}
+
+pub fn div_ceil(a: i32, b: i32) -> i32 {
+    -div_floor(-a, b)
+}
+
+/// Scanline rasterization of a polygon with corners in sub-pixel coordinates.
+///
+/// Calls `span(y, x_start, x_end)` for every covered run of pixels, `x_end` is
+/// exclusive. A pixel is covered if its center lies inside the polygon, centers
+/// exactly on a left or top edge are inside while those on a right or bottom edge
+/// are not (top-left rule). That way polygons sharing an edge cover every pixel
+/// along it exactly once.
+pub fn rasterize_polygon<F: FnMut(i32, i32, i32)>(points: &[(i32, i32)], mut span: F) {
+    if points.len() < 3 {
+        return;
+    }
+
+    let mut min_y = points[0].1;
+    let mut max_y = points[0].1;
+    for p in points.iter() {
+        if p.1 < min_y {
+            min_y = p.1;
+        }
+        if p.1 > max_y {
+            max_y = p.1;
+        }
+    }
+
+    let half = SUBPIXEL / 2;
+    for py in div_ceil(min_y - half, SUBPIXEL)..div_ceil(max_y - half, SUBPIXEL) {
+        let center_y = py * SUBPIXEL + half;
+
+        let mut crossings = [0i32; MAX_CROSSINGS];
+        let mut count = 0;
+        for i in 0..points.len() {
+            let mut a = points[i];
+            let mut b = points[(i + 1) % points.len()];
+            if a.1 == b.1 {
+                continue;
+            }
+            // always evaluate an edge in the same direction, so that neighbouring
+            // polygons get exactly the same crossings on a shared edge
+            if a.1 > b.1 {
+                let t = a;
+                a = b;
+                b = t;
+            }
+            if center_y < a.1 || center_y >= b.1 {
+                continue;
+            }
+            if count < MAX_CROSSINGS {
+                crossings[count] = a.0 + div_floor((center_y - a.1) * (b.0 - a.0), b.1 - a.1);
+                count += 1;
+            }
+        }
+
+        crossings[..count].sort_unstable();
+        let mut i = 0;
+        while i + 1 < count {
+            let x_start = div_ceil(crossings[i] - half, SUBPIXEL);
+            let x_end = div_ceil(crossings[i + 1] - half, SUBPIXEL);
+            if x_start < x_end {
+                span(py, x_start, x_end);
+            }
+            i += 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: i32 = 512;
+    // polygons are moved by this, so negative coordinates are counted as well
+    const OFFSET: i32 = 128;
+
+    /// Counts how often each pixel is covered by the polygons, given in
+    /// sub-pixel coordinates.
+    fn coverage(polygons: &[Vec<(i32, i32)>]) -> Vec<u8> {
+        let mut counts = vec![0u8; (SIZE * SIZE) as usize];
+        for polygon in polygons.iter() {
+            rasterize_polygon(polygon, |y, x0, x1| {
+                for x in x0..x1 {
+                    let (cx, cy) = (x + OFFSET, y + OFFSET);
+                    assert!(cx >= 0 && cx < SIZE && cy >= 0 && cy < SIZE);
+                    counts[(cx + cy * SIZE) as usize] += 1;
+                }
+            });
+        }
+        counts
+    }
+
+    fn fixed(points: &[(i32, i32)]) -> Vec<(i32, i32)> {
+        let (fixed, count) = to_fixed_point(points);
+        fixed[..count].to_vec()
+    }
+
+    /// Asserts that the pieces cover exactly the pixels of the whole, each once.
+    fn assert_tiles(whole: &[(i32, i32)], pieces: &[Vec<(i32, i32)>]) {
+        let expected = coverage(&[whole.to_vec()]);
+        let actual = coverage(pieces);
+        for i in 0..expected.len() {
+            let (x, y) = (i as i32 % SIZE - OFFSET, i as i32 / SIZE - OFFSET);
+            assert!(actual[i] <= 1, "pixel {:?} covered {} times", (x, y), actual[i]);
+            assert!(expected[i] == actual[i], "pixel {:?} not covered", (x, y));
+        }
+    }
+
+    #[test]
+    fn block_faces_cover_every_pixel_once() {
+        for &(width, height, depth) in [(100, 60, 100), (100, 15, 100), (37, 15, 61), (4, 15, 4), (5, 1, 3)].iter() {
+            for &(x, y) in [(0, 0), (-13, 7), (21, -40)].iter() {
+                let faces = block_faces(x, y, width, height, depth);
+                let (left, back, right, front) = (faces[0][0], faces[0][1], faces[0][2], faces[0][3]);
+                let outline = fixed(&[
+                    left,
+                    back,
+                    right,
+                    (right.0, right.1 + height),
+                    (front.0, front.1 + height),
+                    (left.0, left.1 + height),
+                ]);
+                let pieces: Vec<_> = faces.iter().map(|face| fixed(face)).collect();
+                assert_tiles(&outline, &pieces);
+            }
+        }
+    }
+
+    #[test]
+    fn subpixel_triangles_around_a_point_tile() {
+        // a fan of triangles with corners between the pixel centers
+        let center = (1000, 1213);
+        let corners = [(77, 91), (1550, 3), (2421, 980), (2290, 2207), (1003, 2450), (13, 1800)];
+        let mut pieces = Vec::new();
+        for i in 0..corners.len() {
+            pieces.push(vec![center, corners[i], corners[(i + 1) % corners.len()]]);
+        }
+        assert_tiles(&corners, &pieces);
+    }
+
+    #[test]
+    fn quads_with_shared_corners_tile() {
+        // a 4x4 grid of quads in a square, the inner corners are moved around
+        let cell = 40 * SUBPIXEL;
+        let corner = |i: i32, j: i32| {
+            let inner = i > 0 && i < 4 && j > 0 && j < 4;
+            let jitter = if inner { (i * 37 + j * 11) % 150 - 75 } else { 0 };
+            (i * cell + jitter, j * cell - jitter / 2)
+        };
+        let mut pieces = Vec::new();
+        for j in 0..4 {
+            for i in 0..4 {
+                pieces.push(vec![corner(i, j), corner(i + 1, j), corner(i + 1, j + 1), corner(i, j + 1)]);
+            }
+        }
+        let square = [(0, 0), (4 * cell, 0), (4 * cell, 4 * cell), (0, 4 * cell)];
+        assert_tiles(&square, &pieces);
+    }
+
+    #[test]
+    #[should_panic]
+    fn too_many_corners_are_rejected() {
+        to_fixed_point(&[(0, 0); MAX_POLYGON_POINTS + 1]);
+    }
+}
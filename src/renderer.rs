@@ -6,15 +6,72 @@ extern crate alloc;
 use stm32f7::lcd;
 use stm32f7::lcd::Color;
 use stm32f7::lcd::font::FontRenderer;
+use alloc::Vec;
 use alloc::boxed::Box;
+use core::ptr;
 
-const WIDTH: i32 = 480;
-const HEIGHT: i32 = 272;
+use background::Background;
+use backend::{Backend, SoftwareBackend, Surface};
+use double_buffer::DoubleBuffer;
+use image::Image;
+use orientation::Orientation;
+use panel;
+use pixel_format::{PanelFormat, PixelFormat};
+use profiler;
+use raster::{block_faces, div_ceil, div_floor, rasterize_polygon, to_fixed_point, SUBPIXEL};
+use sdram_buffer::SdramBuffer;
+use vsync;
 
 const PIXEL_BUFFER_SIZE: usize = 3000;
 
+const MAX_CLIP_DEPTH: usize = 8;
+
+/// Rectangle in screen coordinates of the current orientation.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x0 = if self.x > other.x { self.x } else { other.x };
+        let y0 = if self.y > other.y { self.y } else { other.y };
+        let x1 = if self.x + self.width < other.x + other.width { self.x + self.width } else { other.x + other.width };
+        let y1 = if self.y + self.height < other.y + other.height { self.y + self.height } else { other.y + other.height };
+        if x1 <= x0 || y1 <= y0 {
+            Rect::new(x0, y0, 0, 0)
+        } else {
+            Rect::new(x0, y0, x1 - x0, y1 - y0)
+        }
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        !self.intersect(other).is_empty()
+    }
+}
+
 pub struct Renderer<'a, T: lcd::Framebuffer + 'a> {
-    pixel_markers: [u32; ((WIDTH * HEIGHT + 31) / 32) as usize],
+    pixel_markers: Vec<u32>,
     drawn_pixels_x: [i16; 2 * PIXEL_BUFFER_SIZE],
     drawn_pixels_y: [i16; 2 * PIXEL_BUFFER_SIZE],
     drawn_pixels_color: [Color; 2 * PIXEL_BUFFER_SIZE],
@@ -23,17 +80,43 @@ pub struct Renderer<'a, T: lcd::Framebuffer + 'a> {
     layer: &'a mut lcd::Layer<T>,
     direct: bool,
     frame_counter: i32,
-    portrait: bool,
+    orientation: Orientation,
+    panel_width: i32,
+    panel_height: i32,
+    // size of the screen in the current orientation
     width: i32,
     height: i32,
-    bg_func: Box<FnMut(i32, i32) -> Color>,
-    immediate: bool
+    background: Box<Background>,
+    immediate: bool,
+    panel_format: PanelFormat,
+    bg_cache: Option<SdramBuffer>,
+    framebuffer_address: Option<usize>,
+    backend: Box<Backend>,
+    double_buffer: Option<DoubleBuffer>,
+    vsync: bool,
+    antialiasing: bool,
+    clip_stack: [Rect; MAX_CLIP_DEPTH],
+    clip_depth: usize,
+    clip: Rect,
+    // the clip rectangle in layer coordinates as x0, y0, x1, y1
+    clip_bounds: (i32, i32, i32, i32),
+    // cycles spent restoring the background since the last `take_restore_cycles`
+    restore_cycles: u32,
 }
 
 impl<'a, T: lcd::Framebuffer> Renderer<'a, T> {
-    pub fn new(l: &'a mut lcd::Layer<T>, background: Box<FnMut(i32, i32) -> Color>) -> Renderer<T> {
+    /// Creates a renderer for the panel the LTDC is configured for.
+    pub fn new(l: &'a mut lcd::Layer<T>, background: Box<Background>) -> Renderer<T> {
+        let (width, height) = panel::size();
+        Renderer::with_size(l, background, width, height)
+    }
+
+    /// Creates a renderer for a panel of the given size in landscape orientation.
+    pub fn with_size(l: &'a mut lcd::Layer<T>, background: Box<Background>, width: i32, height: i32) -> Renderer<T> {
+        let mut pixel_markers = Vec::new();
+        pixel_markers.resize(((width * height + 31) / 32) as usize, 0);
         Renderer {
-            pixel_markers: [0; ((WIDTH * HEIGHT + 31) / 32) as usize],
+            pixel_markers: pixel_markers,
             drawn_pixels_x: [0; 2 * PIXEL_BUFFER_SIZE],
             drawn_pixels_y: [0; 2 * PIXEL_BUFFER_SIZE],
             drawn_pixels_color: [Color::rgb(0, 0, 0); 2 * PIXEL_BUFFER_SIZE],
@@ -42,24 +125,242 @@ impl<'a, T: lcd::Framebuffer> Renderer<'a, T> {
             layer: l,
             direct: true,
             frame_counter: 0,
-            portrait: false,
-            width: WIDTH,
-            height: HEIGHT,
-            bg_func: background,
+            orientation: Orientation::Landscape,
+            panel_width: width,
+            panel_height: height,
+            width: width,
+            height: height,
+            background: background,
             immediate: false,
+            panel_format: PanelFormat::plain(PixelFormat::Argb8888),
+            bg_cache: None,
+            framebuffer_address: None,
+            backend: Box::new(SoftwareBackend),
+            double_buffer: None,
+            vsync: false,
+            antialiasing: false,
+            clip_stack: [Rect::new(0, 0, width, height); MAX_CLIP_DEPTH],
+            clip_depth: 0,
+            clip: Rect::new(0, 0, width, height),
+            clip_bounds: (0, 0, width, height),
+            restore_cycles: 0,
+        }
+    }
+
+    /// Creates a renderer that draws every frame into the back buffer of
+    /// `double_buffer` instead of tracking the changed pixels. Falls back to
+    /// the pixel tracking if no double buffer is given.
+    pub fn with_double_buffer(
+        l: &'a mut lcd::Layer<T>,
+        background: Box<Background>,
+        double_buffer: Option<DoubleBuffer>,
+    ) -> Renderer<T> {
+        let (width, height) = match double_buffer {
+            Some(ref double_buffer) => (double_buffer.width(), double_buffer.height()),
+            None => panel::size(),
+        };
+        let mut renderer = Renderer::with_size(l, background, width, height);
+        if double_buffer.is_some() {
+            renderer.double_buffer = double_buffer;
+            renderer.clear();
+        }
+        renderer
+    }
+
+    pub fn is_double_buffered(&self) -> bool {
+        self.double_buffer.is_some()
+    }
+
+    pub fn set_bg(&mut self, background: Box<Background>) {
+        self.background = background;
+        self.render_bg_cache();
+    }
+
+    pub fn set_bg_scroll(&mut self, scroll: i32) {
+        self.background.set_scroll(scroll);
+        self.render_bg_cache();
+    }
+
+    /// Pre-renders the background into `buffer` whenever it changes, so restoring
+    /// the background is a memory read instead of an evaluation of the background.
+    pub fn enable_bg_cache(&mut self, buffer: SdramBuffer) {
+        assert!(buffer.len() >= (self.panel_width * self.panel_height) as usize);
+        self.bg_cache = Some(buffer);
+        self.render_bg_cache();
+    }
+
+    pub fn disable_bg_cache(&mut self) -> Option<SdramBuffer> {
+        self.bg_cache.take()
+    }
+
+    /// Address of the layer's framebuffer, which allows copying cached background
+    /// pixels directly into it. Only used for 32 bit pixel formats.
+    pub fn set_framebuffer_address(&mut self, address: usize) {
+        self.framebuffer_address = Some(address);
+    }
+
+    /// Sets the implementation of the block operations that are used when the
+    /// framebuffer address is known.
+    pub fn set_backend(&mut self, backend: Box<Backend>) {
+        self.backend = backend;
+    }
+
+    fn render_bg_cache(&mut self) {
+        if self.bg_cache.is_none() {
+            return;
+        }
+        for y in 0..self.panel_height {
+            for x in 0..self.panel_width {
+                let color = self.get_background(x, y);
+                let value = self.panel_format.encode(color);
+                if let Some(ref mut cache) = self.bg_cache {
+                    cache.write((x + y * self.panel_width) as usize, value);
+                }
+            }
+        }
+    }
+
+    fn direct_framebuffer(&self) -> Option<*mut u32> {
+        if self.panel_format.format.bytes_per_pixel() != 4 {
+            return None;
+        }
+        self.framebuffer_address.map(|address| address as *mut u32)
+    }
+
+    /// Surfaces that block operations have to write to, if they are known.
+    fn block_targets(&self) -> [Option<Surface>; 2] {
+        match self.double_buffer {
+            Some(ref double_buffer) => if self.direct {
+                [
+                    Some(Surface::new(double_buffer.static_address(), self.panel_width)),
+                    Some(Surface::new(double_buffer.front_address(), self.panel_width)),
+                ]
+            } else {
+                [Some(Surface::new(double_buffer.back_address(), self.panel_width)), None]
+            },
+            None => [
+                self.direct_framebuffer()
+                    .map(|framebuffer| Surface::new(framebuffer as usize, self.panel_width)),
+                None,
+            ],
+        }
+    }
+
+    fn restore_background(&mut self, x: i32, y: i32) {
+        let index = (x + y * self.panel_width) as usize;
+        let cached = match self.bg_cache {
+            Some(ref cache) => Some(cache.read(index)),
+            None => None,
+        };
+        let value = match cached {
+            Some(value) => value,
+            None => {
+                let color = self.get_background(x, y);
+                self.panel_format.encode(color)
+            }
+        };
+        self.write_raw(x as usize, y as usize, value);
+    }
+
+    fn restore_background_rect(&mut self, x: i32, y: i32, w: i32, h: i32) {
+        let start = profiler::cycles();
+        self.restore_background_rect_untimed(x, y, w, h);
+        self.restore_cycles = self.restore_cycles.wrapping_add(profiler::cycles().wrapping_sub(start));
+    }
+
+    fn restore_background_rect_untimed(&mut self, x: i32, y: i32, w: i32, h: i32) {
+        let x0 = if x < 0 { 0 } else { x };
+        let y0 = if y < 0 { 0 } else { y };
+        let x1 = if x + w > self.panel_width { self.panel_width } else { x + w };
+        let y1 = if y + h > self.panel_height { self.panel_height } else { y + h };
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+
+        let targets = self.block_targets();
+        if self.bg_cache.is_some() && targets[0].is_some() {
+            let source = Surface::new(self.bg_cache.as_ref().unwrap().address(), self.panel_width);
+            for target in targets.iter() {
+                if let Some(ref target) = *target {
+                    self.backend.copy(&source, target, x0, y0, x1 - x0, y1 - y0);
+                }
+            }
+        } else {
+            for py in y0..y1 {
+                for px in x0..x1 {
+                    self.restore_background(px, py);
+                }
+            }
         }
     }
 
-    pub fn set_bg(&mut self, func: Box<FnMut(i32, i32) -> Color>) {
-        self.bg_func = func;
+    /// Returns the cycles spent restoring the background since the last call.
+    pub fn take_restore_cycles(&mut self) -> u32 {
+        let cycles = self.restore_cycles;
+        self.restore_cycles = 0;
+        cycles
+    }
+
+    /// Abandons a frame that might be in progress, so everything is drawn right
+    /// away without clipping. Used for the crash screen.
+    pub fn set_direct(&mut self) {
+        self.direct = true;
+        self.reset_clip();
     }
 
     pub fn set_immediate(&mut self, state: bool) {
         self.immediate = state;
     }
 
+    /// Lets `end_frame` wait for the vertical blanking period before it updates
+    /// the layer. Double buffered renderers are always synchronised.
+    pub fn set_vsync(&mut self, state: bool) {
+        self.vsync = state;
+    }
+
+    /// Smoothes the slanted edges of blocks by blending them with the background.
+    pub fn set_antialiasing(&mut self, state: bool) {
+        self.antialiasing = state;
+    }
+
+    pub fn set_panel_format(&mut self, format: PanelFormat) {
+        self.panel_format = format;
+    }
+
+    pub fn get_panel_format(&self) -> PanelFormat {
+        self.panel_format
+    }
+
+    fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let value = self.panel_format.encode(color);
+        self.write_raw(x, y, value);
+    }
+
+    /// Writes a pixel value that is already encoded for the panel.
+    fn write_raw(&mut self, x: usize, y: usize, value: u32) {
+        let index = x + y * self.panel_width as usize;
+        if let Some(ref mut double_buffer) = self.double_buffer {
+            if self.direct {
+                double_buffer.write_static(index, value);
+            } else {
+                double_buffer.write_back(index, value);
+            }
+            return;
+        }
+
+        match self.direct_framebuffer() {
+            Some(framebuffer) => unsafe {
+                ptr::write_volatile(framebuffer.offset(index as isize), value);
+            },
+            None => {
+                let color = self.panel_format.format.decode(value);
+                self.layer.print_point_color_at(x, y, color);
+            }
+        }
+    }
+
     fn mark_pixel(&mut self, x: i32, y: i32, state: bool) {
-        let index = x + y * WIDTH;
+        let index = x + y * self.panel_width;
         let mask = 1 << (index % 32);
         if state {
             self.pixel_markers[(index / 32) as usize] |= mask;
@@ -69,27 +370,22 @@ impl<'a, T: lcd::Framebuffer> Renderer<'a, T> {
     }
 
     fn is_pixel_marked(&mut self, x: i32, y: i32) -> bool {
-        let index = x + y * WIDTH;
+        let index = x + y * self.panel_width;
         let mask = 1 << (index % 32);
         let marker = self.pixel_markers[(index / 32) as usize];
         marker & mask != 0
     }
 
     pub fn set_pixel(&mut self, px: i32, py: i32, color: Color) {
-        let mut x = px;
-        let mut y = py;
-
-        if self.portrait {
-            x = WIDTH - py;
-            y = px;
-        }
+        let (x, y) = self.orientation.to_panel(px, py, self.panel_width, self.panel_height);
 
-        if x < 0 || x >= WIDTH || y < 0 || y >= HEIGHT {
+        let (x0, y0, x1, y1) = self.clip_bounds;
+        if x < x0 || x >= x1 || y < y0 || y >= y1 {
             return;
         }
 
-        if self.direct {
-            self.layer.print_point_color_at(x as usize, y as usize, color);
+        if self.direct || self.double_buffer.is_some() {
+            self.write_pixel(x as usize, y as usize, color);
         } else {
             self.mark_pixel(x, y, true);
             let offset = self.current_buffer as usize * PIXEL_BUFFER_SIZE;
@@ -100,12 +396,25 @@ impl<'a, T: lcd::Framebuffer> Renderer<'a, T> {
             self.drawn_pixel_count[self.current_buffer as usize] += 1;
 
             if self.immediate {
-                self.layer.print_point_color_at(x as usize, y as usize, color);
+                self.write_pixel(x as usize, y as usize, color);
             }
         }
     }
 
     pub fn begin_frame(&mut self) {
+        let back = match self.double_buffer {
+            Some(ref double_buffer) => Some((
+                Surface::new(double_buffer.static_address(), self.panel_width),
+                Surface::new(double_buffer.back_address(), self.panel_width),
+            )),
+            None => None,
+        };
+        if let Some((static_surface, back_surface)) = back {
+            self.backend.copy(&static_surface, &back_surface, 0, 0, self.panel_width, self.panel_height);
+            self.direct = false;
+            return;
+        }
+
         let last_buffer = 1 - self.current_buffer;
         let offset = last_buffer as usize * PIXEL_BUFFER_SIZE;
         let size = self.drawn_pixel_count[last_buffer as usize];
@@ -119,6 +428,17 @@ impl<'a, T: lcd::Framebuffer> Renderer<'a, T> {
     }
 
     pub fn end_frame(&mut self) {
+        if let Some(ref mut double_buffer) = self.double_buffer {
+            double_buffer.present();
+            self.frame_counter += 1;
+            self.direct = true;
+            return;
+        }
+
+        if self.vsync {
+            vsync::wait_for_vsync();
+        }
+
         let last_buffer = 1 - self.current_buffer;
         let last_offset = last_buffer as usize * PIXEL_BUFFER_SIZE;
         let last_size = self.drawn_pixel_count[last_buffer as usize];
@@ -126,28 +446,26 @@ impl<'a, T: lcd::Framebuffer> Renderer<'a, T> {
         let offset = self.current_buffer as usize * PIXEL_BUFFER_SIZE;
         let size = self.drawn_pixel_count[self.current_buffer as usize];
 
-        let mut max_size = last_size;
-        if size > max_size && !self.immediate {
-            max_size = size;
-        }
-
-        for i in 0..max_size {
-            if i < size && !self.immediate {
+        if !self.immediate {
+            for i in 0..size {
                 let x = self.drawn_pixels_x[(i + offset) as usize] as i32;
                 let y = self.drawn_pixels_y[(i + offset) as usize] as i32;
                 let color = self.drawn_pixels_color[(i + offset) as usize];
-                self.layer
-                    .print_point_color_at(x as usize, y as usize, color);
+                self.write_pixel(x as usize, y as usize, color);
             }
-            if i < last_size {
-                let x = self.drawn_pixels_x[(i + last_offset) as usize] as i32;
-                let y = self.drawn_pixels_y[(i + last_offset) as usize] as i32;
-                if !self.is_pixel_marked(x, y) {
-                    let color = self.get_background(x, y);
-                    self.layer.print_point_color_at(x as usize, y as usize, color);
-                }
+        }
+
+        // pixels of the last frame that were not drawn again, the new pixels
+        // are marked so the order of both passes does not matter
+        let start = profiler::cycles();
+        for i in 0..last_size {
+            let x = self.drawn_pixels_x[(i + last_offset) as usize] as i32;
+            let y = self.drawn_pixels_y[(i + last_offset) as usize] as i32;
+            if !self.is_pixel_marked(x, y) {
+                self.restore_background(x, y);
             }
         }
+        self.restore_cycles = self.restore_cycles.wrapping_add(profiler::cycles().wrapping_sub(start));
 
         self.current_buffer = last_buffer;
         self.frame_counter += 1;
@@ -155,71 +473,163 @@ impl<'a, T: lcd::Framebuffer> Renderer<'a, T> {
     }
 
     pub fn flush(&mut self) {
+        let start = profiler::cycles();
         for buf in 0..2 {
             let offset = buf as usize * PIXEL_BUFFER_SIZE;
             let size = self.drawn_pixel_count[buf as usize];
             for i in 0..size {
                 let x = self.drawn_pixels_x[(i + offset) as usize] as i32;
                 let y = self.drawn_pixels_y[(i + offset) as usize] as i32;
-                let color = self.get_background(x, y);
-                self.layer
-                    .print_point_color_at(x as usize, y as usize, color);
+                self.restore_background(x, y);
                 self.mark_pixel(x, y, false);
             }
             self.drawn_pixel_count[buf as usize] = 0;
         }
+        self.restore_cycles = self.restore_cycles.wrapping_add(profiler::cycles().wrapping_sub(start));
     }
 
     pub fn clear(&mut self) {
         self.flush();
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                let color = self.get_background(x, y);
-                self.layer
-                    .print_point_color_at(x as usize, y as usize, color);
-            }
-        }
+        self.restore_background_rect(0, 0, self.panel_width, self.panel_height);
     }
 
     pub fn clear_area(&mut self, x: i32, y: i32, w: i32, h: i32) {
-        if self.portrait {
-            self.clear_area_landscape(WIDTH - y - h, x, h, w);
-        } else {
-            self.clear_area_landscape(x, y, w, h);
+        let (px, py, pw, ph) = self.rect_to_panel(x, y, w, h);
+        self.restore_background_rect(px, py, pw, ph);
+    }
+
+    fn rect_to_panel(&self, x: i32, y: i32, w: i32, h: i32) -> (i32, i32, i32, i32) {
+        self.orientation.rect_to_panel(x, y, w, h, self.panel_width, self.panel_height)
+    }
+
+    /// Fills rectangles with the backend if the renderer draws into a known
+    /// framebuffer. Returns false if the caller has to draw the pixels itself.
+    fn fill_rects_fast(&mut self, rects: &[Rect], color: Color) -> bool {
+        if !self.direct && self.double_buffer.is_none() {
+            return false;
+        }
+        let targets = self.block_targets();
+        if targets[0].is_none() {
+            return false;
+        }
+
+        let mut panel_rects = [(0, 0, 0, 0); SPAN_BATCH];
+        let mut count = 0;
+        let (cx0, cy0, cx1, cy1) = self.clip_bounds;
+        for rect in rects.iter() {
+            let (lx, ly, lw, lh) = self.rect_to_panel(rect.x, rect.y, rect.width, rect.height);
+            let x0 = if lx < cx0 { cx0 } else { lx };
+            let y0 = if ly < cy0 { cy0 } else { ly };
+            let x1 = if lx + lw > cx1 { cx1 } else { lx + lw };
+            let y1 = if ly + lh > cy1 { cy1 } else { ly + lh };
+            if x0 < x1 && y0 < y1 && count < SPAN_BATCH {
+                panel_rects[count] = (x0, y0, x1 - x0, y1 - y0);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            let value = self.panel_format.encode(color);
+            for target in targets.iter() {
+                if let Some(ref target) = *target {
+                    self.backend.fill_rects(target, &panel_rects[..count], value);
+                }
+            }
         }
+        true
     }
 
-    fn clear_area_landscape(&mut self, x: i32, y: i32, w: i32, h: i32) {
-        for py in y..y + h {
-            for px in x..x + w {
-                let color = self.get_background(px, py);
-                self.layer
-                    .print_point_color_at(px as usize, py as usize, color);
+    fn fill_rects(&mut self, rects: &[Rect], color: Color) {
+        if rects.is_empty() || self.fill_rects_fast(rects, color) {
+            return;
+        }
+        for rect in rects.iter() {
+            for py in rect.y..rect.y + rect.height {
+                for px in rect.x..rect.x + rect.width {
+                    self.set_pixel(px, py, color);
+                }
             }
         }
     }
 
+    /// Returns the background color of a panel pixel.
     pub fn get_background(&mut self, px: i32, py: i32) -> Color {
-        let mut x = px;
-        let mut y = py;
-        if self.portrait {
-            x = py;
-            y = WIDTH - px;
+        let (x, y) = self.orientation.from_panel(px, py, self.panel_width, self.panel_height);
+        self.background.color(x, y)
+    }
+
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+        let (width, height) = orientation.screen_size(self.panel_width, self.panel_height);
+        self.width = width;
+        self.height = height;
+        self.reset_clip();
+        self.render_bg_cache();
+    }
+
+    pub fn get_orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// Restricts all drawing to the intersection of `rect` with the current clip
+    /// rectangle until the matching `pop_clip`.
+    pub fn push_clip(&mut self, rect: Rect) {
+        assert!(self.clip_depth < MAX_CLIP_DEPTH, "clip stack overflow");
+        self.clip_stack[self.clip_depth] = self.clip;
+        self.clip_depth += 1;
+        self.clip = self.clip.intersect(&rect);
+        self.update_clip_bounds();
+    }
+
+    pub fn pop_clip(&mut self) {
+        if self.clip_depth > 0 {
+            self.clip_depth -= 1;
+            self.clip = self.clip_stack[self.clip_depth];
+            self.update_clip_bounds();
         }
-        (self.bg_func)(x, y)
     }
 
-    pub fn set_portrait(&mut self, state: bool) {
-        self.portrait = state;
-        if state {
-            self.width = HEIGHT;
-            self.height = WIDTH;
+    pub fn get_clip(&self) -> Rect {
+        self.clip
+    }
+
+    /// Drops all clip rectangles, e.g. after the orientation changed.
+    pub fn reset_clip(&mut self) {
+        self.clip_depth = 0;
+        self.clip = Rect::new(0, 0, self.width, self.height);
+        self.update_clip_bounds();
+    }
+
+    fn update_clip_bounds(&mut self) {
+        let (x, y, w, h) = self.rect_to_panel(self.clip.x, self.clip.y, self.clip.width, self.clip.height);
+        let bounds = Rect::new(x, y, w, h).intersect(&Rect::new(0, 0, self.panel_width, self.panel_height));
+        self.clip_bounds = (bounds.x, bounds.y, bounds.x + bounds.width, bounds.y + bounds.height);
+    }
+
+    /// Returns true if nothing inside of the given rectangle can be drawn.
+    fn is_clipped(&self, x: i32, y: i32, w: i32, h: i32) -> bool {
+        !self.clip.intersects(&Rect::new(x, y, w, h))
+    }
+
+    /// Clips the horizontal span [`x0`, `x1`) on line `y`.
+    fn clip_span(&self, y: i32, x0: i32, x1: i32) -> Option<(i32, i32)> {
+        let clip = self.clip;
+        if y < clip.y || y >= clip.y + clip.height {
+            return None;
+        }
+        let start = if x0 < clip.x { clip.x } else { x0 };
+        let end = if x1 > clip.x + clip.width { clip.x + clip.width } else { x1 };
+        if start < end {
+            Some((start, end))
         } else {
-            self.width = WIDTH;
-            self.height = HEIGHT;
+            None
         }
     }
 
+    /// Maps a touch position on the panel to screen coordinates.
+    pub fn touch_position(&self, tx: i32, ty: i32) -> (i32, i32) {
+        self.orientation.from_panel(tx, ty, self.panel_width, self.panel_height)
+    }
+
     pub fn get_width(&self) -> i32 {
         return self.width;
     }
@@ -228,52 +638,236 @@ impl<'a, T: lcd::Framebuffer> Renderer<'a, T> {
         return self.height;
     }
 
+    /// Size of the panel independent of the orientation.
+    pub fn get_panel_size(&self) -> (i32, i32) {
+        (self.panel_width, self.panel_height)
+    }
+
     pub fn draw_block_3d(&mut self, x: i32, y: i32, width: i32, height: i32, depth: i32, color: Color) {
-        self.draw_line(x, y, x + width, y + width / 2, color);
-        self.draw_line(x + width, y + width / 2, x + width + depth, y + width / 2 - depth / 2, color);
-        self.draw_line(x, y, x + depth, y - depth / 2, color);
-        self.draw_line(x + depth, y - depth / 2, x + width + depth, y + width / 2 - depth / 2, color);
+        if self.is_block_clipped(x, y, width, height, depth) {
+            return;
+        }
+        self.draw_edge(x, y, x + width, y + width / 2, color);
+        self.draw_edge(x + width, y + width / 2, x + width + depth, y + width / 2 - depth / 2, color);
+        self.draw_edge(x, y, x + depth, y - depth / 2, color);
+        self.draw_edge(x + depth, y - depth / 2, x + width + depth, y + width / 2 - depth / 2, color);
 
-        self.draw_line(x, y + height, x + width, y + width / 2 + height, color);
-        self.draw_line(x + width, y + width / 2 + height, x + width + depth, y + width / 2 - depth / 2 + height, color);
+        self.draw_edge(x, y + height, x + width, y + width / 2 + height, color);
+        self.draw_edge(x + width, y + width / 2 + height, x + width + depth, y + width / 2 - depth / 2 + height, color);
 
-        self.draw_line(x, y, x, y + height, color);
-        self.draw_line(x + width - 1, y + width / 2, x + width - 1, y + width / 2 + height, color);
-        self.draw_line(x + width + depth, y + width / 2 - depth / 2, x + width + depth, y + width / 2 - depth / 2 + height, color);
+        self.draw_edge(x, y, x, y + height, color);
+        self.draw_edge(x + width - 1, y + width / 2, x + width - 1, y + width / 2 + height, color);
+        self.draw_edge(x + width + depth, y + width / 2 - depth / 2, x + width + depth, y + width / 2 - depth / 2 + height, color);
     }
 
     pub fn draw_block_3d_solid(&mut self, x: i32, y: i32, width: i32, height: i32, depth: i32, left_color: Color, right_color: Color, top_color: Color) {
-        self.draw_triangle_solid_left_to_right(x, y, x + depth, y - depth / 2, x + depth + width, y - depth / 2 + width / 2, top_color);
-        self.draw_triangle_solid_left_to_right(x, y, x + width, y + width / 2, x + depth + width, y - depth / 2 + width / 2, top_color);
-        
-        self.draw_y_oblique(x, y + 1, width + 1, height, height, width / 2, left_color);
-        self.draw_y_oblique(x + width + 1, y + width / 2, depth + 1, height, height, -depth / 2, right_color);
+        self.draw_block_3d_shaded(x, y, width, height, depth, left_color, left_color, right_color, right_color, top_color);
+    }
+
+    pub fn draw_block_3d_shaded(&mut self, x: i32, y: i32, width: i32, height: i32, depth: i32, left_top: Color, left_bottom: Color, right_top: Color, right_bottom: Color, top_color: Color) {
+        if self.is_block_clipped(x, y, width, height, depth) {
+            return;
+        }
+        let faces = block_faces(x, y, width, height, depth);
+        let (top_face, left_face, right_face) = (faces[0], faces[1], faces[2]);
+        let (left, back, right, front) = (top_face[0], top_face[1], top_face[2], top_face[3]);
+
+        self.fill_polygon(&top_face, top_color);
+
+        self.fill_polygon_with(&left_face, |px, py| {
+            let top = y + (px - x) / 2;
+            mix_color(left_top, left_bottom, clamp_unit((py - top) as f32 / height as f32))
+        });
+
+        self.fill_polygon_with(&right_face, |px, py| {
+            let top = front.1 - (px - front.0) / 2;
+            mix_color(right_top, right_bottom, clamp_unit((py - top) as f32 / height as f32))
+        });
+
+        if self.antialiasing {
+            // the top edges are the silhouette against the background, the bottom
+            // edges usually lie on the block below, so they are blended with what
+            // is already drawn there
+            self.smooth_edge(left, back, top_color, true, false);
+            self.smooth_edge(back, right, top_color, true, false);
+            self.smooth_edge(left_face[3], left_face[2], left_bottom, false, true);
+            self.smooth_edge(right_face[3], right_face[2], right_bottom, false, true);
+        }
     }
 
-    pub fn draw_triangle_solid_left_to_right(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, x2: i32, y2: i32, color: Color) {
-        let p = x1; // intersection x
-        let q = y0 + (y2 - y0) * (2 * (x1 - x0) + 1) / (2 * (x2 - x0)); // intersection y
+    /// Blends `color` with the background according to `coverage`. On a transparent
+    /// background the coverage becomes the alpha value, so the LTDC does the blending.
+    fn blend_with_background(&mut self, px: i32, py: i32, color: Color, coverage: f32) -> Color {
+        let (x, y) = self.orientation.to_panel(px, py, self.panel_width, self.panel_height);
+        if x < 0 || x >= self.panel_width || y < 0 || y >= self.panel_height {
+            return color;
+        }
+        let background = self.get_background(x, y);
+        if background.alpha == 0 {
+            Color::rgba(color.red, color.green, color.blue, (coverage * color.alpha as f32) as u8)
+        } else {
+            mix_color(background, color, coverage)
+        }
+    }
+
+    /// Anti-aliases the outside of a polygon edge that is not steeper than 45°.
+    ///
+    /// The pixels right outside of the edge are not covered by the rasterizer, they
+    /// are blended with the part of their area that lies inside of the polygon, either
+    /// over the background or over the current content of the framebuffer.
+    fn smooth_edge(&mut self, p0: (i32, i32), p1: (i32, i32), color: Color, outside_above: bool, over_framebuffer: bool) {
+        let (a, b) = if p0.0 <= p1.0 { (p0, p1) } else { (p1, p0) };
+        if a.0 == b.0 {
+            return;
+        }
+        let slope = (b.1 - a.1) as f32 / (b.0 - a.0) as f32;
+        for px in a.0..b.0 {
+            let edge_y = a.1 as f32 + (px as f32 + 0.5f32 - a.0 as f32) * slope;
+            let (py, distance) = if outside_above {
+                let py = ceil(edge_y - 0.5f32) - 1;
+                (py, edge_y - (py as f32 + 0.5f32))
+            } else {
+                let py = ceil(edge_y - 0.5f32);
+                (py, py as f32 + 0.5f32 - edge_y)
+            };
+            let coverage = 0.5f32 - distance;
+            if coverage > 0f32 {
+                let c = if over_framebuffer {
+                    let alpha = (coverage * color.alpha as f32) as u8;
+                    self.blend_over(px, py, Color::rgba(color.red, color.green, color.blue, alpha))
+                } else {
+                    self.blend_with_background(px, py, color, coverage)
+                };
+                self.set_pixel(px, py, c);
+            }
+        }
+    }
 
-        if q < y1 {
-            self.draw_y_oblique(x0, y0, p - x0 + 1, 2, y1 - q + 2, q - y0, color); //draw first triangle
-            self.draw_y_oblique(p, q, x2 - p + 1, y1 - q + 2, 2, y2 - q, color); //draw second triangle
+    fn draw_edge(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+        if self.antialiasing && x0 != x1 && y0 != y1 {
+            self.draw_line_aa(x0, y0, x1, y1, color);
         } else {
-            self.draw_y_oblique(x0, y0, p - x0 + 1, 2, q - y1 + 2, y1 - y0, color); //draw first triangle
-            self.draw_y_oblique(x1, y1, x2 - p + 1, q - y1 + 2, 2, y2 - y1, color); //draw second triangle
+            self.draw_line(x0, y0, x1, y1, color);
+        }
+    }
+
+    /// Draws an anti-aliased line with Xiaolin Wu's algorithm.
+    pub fn draw_line_aa(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+        if self.is_line_clipped(x0, y0, x1, y1) {
+            return;
+        }
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        let (mut x0, mut y0, mut x1, mut y1) = if steep { (y0, x0, y1, x1) } else { (x0, y0, x1, y1) };
+        if x0 > x1 {
+            let t = (x0, y0);
+            x0 = x1;
+            y0 = y1;
+            x1 = t.0;
+            y1 = t.1;
         }
+
+        let gradient = if x1 == x0 { 1f32 } else { (y1 - y0) as f32 / (x1 - x0) as f32 };
+        let mut y = y0 as f32;
+        for x in x0..=x1 {
+            let y_int = floor(y);
+            let fraction = y - y_int as f32;
+            for &(py, coverage) in [(y_int, 1f32 - fraction), (y_int + 1, fraction)].iter() {
+                if coverage <= 0f32 {
+                    continue;
+                }
+                let (px, py) = if steep { (py, x) } else { (x, py) };
+                let c = self.blend_with_background(px, py, color, coverage);
+                self.set_pixel(px, py, c);
+            }
+            y += gradient;
+        }
+    }
+
+    fn is_block_clipped(&self, x: i32, y: i32, width: i32, height: i32, depth: i32) -> bool {
+        // one pixel of margin for outlines and anti-aliasing
+        let top = y - depth / 2 - 1;
+        let bottom = y + width / 2 + height + 2;
+        self.is_clipped(x - 1, top, width + depth + 3, bottom - top)
+    }
+
+    pub fn fill_triangle(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, x2: i32, y2: i32, color: Color) {
+        self.fill_polygon(&[(x0, y0), (x1, y1), (x2, y2)], color);
+    }
+
+    /// Fills a polygon given by its corners in pixel coordinates.
+    pub fn fill_polygon(&mut self, points: &[(i32, i32)], color: Color) {
+        let (fixed, count) = to_fixed_point(points);
+        self.fill_polygon_fixed(&fixed[..count], color);
     }
 
-    pub fn draw_y_oblique(&mut self, x: i32, y:i32, width: i32, height0: i32, height1: i32, y_movement: i32, color: Color) {
-        for i in 0..width {
-            let base_y = y + y_movement * (2 * i + 1) / (2 * width - 2);
-            let h = (height1 - height0) * (2 * i + 1) / (2 * width - 2) + height0;
-            for j in 0..h {
-                self.set_pixel(x + i, base_y + j, color);
+    /// Fills a polygon given by its corners in sub-pixel coordinates (see `SUBPIXEL`).
+    pub fn fill_polygon_fixed(&mut self, points: &[(i32, i32)], color: Color) {
+        if self.is_polygon_clipped(points) {
+            return;
+        }
+        let mut batch = [Rect::new(0, 0, 0, 0); SPAN_BATCH];
+        let mut count = 0;
+        rasterize_polygon(points, |y, x0, x1| {
+            let (x0, x1) = match self.clip_span(y, x0, x1) {
+                Some(span) => span,
+                None => return,
+            };
+            // spans right below each other with the same extent become one rectangle
+            if count > 0 {
+                let last = &mut batch[count - 1];
+                if last.x == x0 && last.width == x1 - x0 && last.y + last.height == y {
+                    last.height += 1;
+                    return;
+                }
             }
+            if count == SPAN_BATCH {
+                self.fill_rects(&batch[..count], color);
+                count = 0;
+            }
+            batch[count] = Rect::new(x0, y, x1 - x0, 1);
+            count += 1;
+        });
+        self.fill_rects(&batch[..count], color);
+    }
+
+    fn is_polygon_clipped(&self, points: &[(i32, i32)]) -> bool {
+        if points.is_empty() {
+            return true;
+        }
+        let (mut x0, mut y0) = points[0];
+        let (mut x1, mut y1) = points[0];
+        for p in points.iter() {
+            if p.0 < x0 { x0 = p.0; }
+            if p.0 > x1 { x1 = p.0; }
+            if p.1 < y0 { y0 = p.1; }
+            if p.1 > y1 { y1 = p.1; }
+        }
+        let x = div_floor(x0, SUBPIXEL);
+        let y = div_floor(y0, SUBPIXEL);
+        self.is_clipped(x, y, div_ceil(x1, SUBPIXEL) - x + 1, div_ceil(y1, SUBPIXEL) - y + 1)
+    }
+
+    /// Fills a polygon given by its corners in pixel coordinates with the color
+    /// returned by `color_at` for every covered pixel.
+    pub fn fill_polygon_with<F: FnMut(i32, i32) -> Color>(&mut self, points: &[(i32, i32)], mut color_at: F) {
+        let (fixed, count) = to_fixed_point(points);
+        if self.is_polygon_clipped(&fixed[..count]) {
+            return;
         }
+        rasterize_polygon(&fixed[..count], |y, x0, x1| {
+            if let Some((x0, x1)) = self.clip_span(y, x0, x1) {
+                for x in x0..x1 {
+                    let color = color_at(x, y);
+                    self.set_pixel(x, y, color);
+                }
+            }
+        });
     }
 
     pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+        if self.is_line_clipped(x0, y0, x1, y1) {
+            return;
+        }
         if y0 == y1 {
             for px in x0..=x1 {
                 self.set_pixel(px, y0, color);
@@ -316,15 +910,24 @@ impl<'a, T: lcd::Framebuffer> Renderer<'a, T> {
         }
     }
 
+    fn is_line_clipped(&self, x0: i32, y0: i32, x1: i32, y1: i32) -> bool {
+        let x = if x0 < x1 { x0 } else { x1 };
+        let y = if y0 < y1 { y0 } else { y1 };
+        // one pixel of margin for anti-aliased lines
+        self.is_clipped(x - 1, y - 1, (x1 - x0).abs() + 3, (y1 - y0).abs() + 3)
+    }
+
     pub fn draw_rect_solid(&mut self, x: i32, y: i32, w: i32, h: i32, color: Color) {
-        for py in y..y + h {
-            for px in x..x + w {
-                self.set_pixel(px, py, color);
-            }
+        let rect = self.clip.intersect(&Rect::new(x, y, w, h));
+        if !rect.is_empty() {
+            self.fill_rects(&[rect], color);
         }
     }
 
     pub fn draw_rect(&mut self, x: i32, y: i32, w: i32, h: i32, color: Color) {
+        if self.is_clipped(x, y, w, h) {
+            return;
+        }
         for px in x..x + w {
             self.set_pixel(px, y, color);
         }
@@ -339,7 +942,76 @@ impl<'a, T: lcd::Framebuffer> Renderer<'a, T> {
         }
     }
 
+    /// Draws an image with its top left corner at (`x`, `y`). Pixels are blended
+    /// according to their alpha value.
+    pub fn draw_image(&mut self, image: &Image, x: i32, y: i32) {
+        if self.is_clipped(x, y, image.width, image.height) {
+            return;
+        }
+
+        let mut px = 0;
+        let mut py = 0;
+        for value in image.pixels() {
+            let alpha = (value >> 24) as u8;
+            if alpha != 0 {
+                let color = Color::rgba((value >> 16) as u8, (value >> 8) as u8, value as u8, alpha);
+                let color = self.blend_over(x + px, y + py, color);
+                self.set_pixel(x + px, y + py, color);
+            }
+            px += 1;
+            if px == image.width {
+                px = 0;
+                py += 1;
+                if y + py >= self.clip.y + self.clip.height {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Reads a pixel back from the framebuffer, if its address is known.
+    fn read_pixel(&self, x: i32, y: i32) -> Option<Color> {
+        let index = (x + y * self.panel_width) as usize;
+        let value = match self.double_buffer {
+            Some(ref double_buffer) => {
+                let address = if self.direct {
+                    double_buffer.static_address()
+                } else {
+                    double_buffer.back_address()
+                };
+                unsafe { ptr::read_volatile((address as *const u32).offset(index as isize)) }
+            }
+            None => match self.direct_framebuffer() {
+                Some(framebuffer) => unsafe { ptr::read_volatile(framebuffer.offset(index as isize)) },
+                None => return None,
+            },
+        };
+        Some(self.panel_format.decode(value))
+    }
+
+    /// Blends a translucent color over the current content of the pixel, or over
+    /// the background if the framebuffer can't be read.
+    fn blend_over(&mut self, px: i32, py: i32, color: Color) -> Color {
+        if color.alpha == 255 {
+            return color;
+        }
+        let (x, y) = self.orientation.to_panel(px, py, self.panel_width, self.panel_height);
+        if x < 0 || x >= self.panel_width || y < 0 || y >= self.panel_height {
+            return color;
+        }
+        let opaque = Color::rgb(color.red, color.green, color.blue);
+        let alpha = color.alpha as f32 / 255f32;
+        match self.read_pixel(x, y) {
+            Some(current) if current.alpha != 0 => mix_color(current, opaque, alpha),
+            _ => self.blend_with_background(px, py, opaque, alpha),
+        }
+    }
+
     pub fn draw_text(&mut self, font: &FontRenderer, text: &str, x: i32, y: i32, color: Color) {
+        // the text extends to the right and downwards only
+        if x >= self.clip.x + self.clip.width || y >= self.clip.y + self.clip.height {
+            return;
+        }
         font.render(text, |px, py, v| {
             let alpha = (255f32 * v) as u8;
             let c = Color::rgba(color.red, color.green, color.blue, alpha);
@@ -348,10 +1020,49 @@ impl<'a, T: lcd::Framebuffer> Renderer<'a, T> {
     }
 }
 
+// polygon spans that are collected before they are handed to the backend
+const SPAN_BATCH: usize = 32;
+
+fn floor(value: f32) -> i32 {
+    let t = value as i32;
+    if (t as f32) > value {
+        t - 1
+    } else {
+        t
+    }
+}
+
+fn ceil(value: f32) -> i32 {
+    let t = value as i32;
+    if (t as f32) < value {
+        t + 1
+    } else {
+        t
+    }
+}
+
+fn clamp_unit(value: f32) -> f32 {
+    if value < 0f32 {
+        0f32
+    } else if value > 1f32 {
+        1f32
+    } else {
+        value
+    }
+}
+
 pub fn weight_color(c: Color, w: f32) -> Color {
     Color::rgb((c.red as f32 * w) as u8, (c.green as f32 * w) as u8, (c.blue as f32 * w) as u8)
 }
 
+pub fn mix_color(a: Color, b: Color, t: f32) -> Color {
+    Color::rgb(
+        ((1f32 - t) * a.red as f32 + t * b.red as f32) as u8,
+        ((1f32 - t) * a.green as f32 + t * b.green as f32) as u8,
+        ((1f32 - t) * a.blue as f32 + t * b.blue as f32) as u8,
+    )
+}
+
 pub fn hsv_color(hue: f32, s: f32, v: f32) -> Color {
     let h = (hue as i32 % 360) as f32;
 
@@ -0,0 +1,166 @@
+#![allow(dead_code)]
+
+extern crate stm32f7_discovery as stm32f7;
+extern crate alloc;
+
+use alloc::Vec;
+use alloc::string::{String, ToString};
+use core::ptr;
+use stm32f7::lcd::Color;
+
+use composite::{self, LayerFormat};
+use panel;
+use pixel_format::{PanelFormat, PixelFormat};
+use semihosting::{self, File};
+
+const LTDC_BASE: usize = 0x4001_6800;
+const LTDC_BCCR: usize = LTDC_BASE + 0x2C;
+const LTDC_LAYERS: [usize; 2] = [LTDC_BASE + 0x84, LTDC_BASE + 0x104];
+
+// offsets of the layer registers
+const LXCR: usize = 0x00;
+const LXPFCR: usize = 0x10;
+const LXCACR: usize = 0x14;
+const LXCFBAR: usize = 0x28;
+const LXCFBLR: usize = 0x2C;
+
+const CR_LEN: u32 = 1 << 0;
+
+/// The configuration of a layer as the LTDC currently shows it.
+struct Layer {
+    format: LayerFormat,
+    address: usize,
+    pitch: usize,
+    constant_alpha: u32,
+}
+
+impl Layer {
+    fn read(base: usize) -> Option<Layer> {
+        unsafe {
+            if read_register(base + LXCR) & CR_LEN == 0 {
+                return None;
+            }
+            Some(Layer {
+                format: LayerFormat::from_register(read_register(base + LXPFCR)),
+                address: read_register(base + LXCFBAR) as usize,
+                pitch: (read_register(base + LXCFBLR) >> 16) as usize,
+                constant_alpha: read_register(base + LXCACR) & 0xFF,
+            })
+        }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> u32 {
+        let address = self.address + y * self.pitch + x * self.format.bytes_per_pixel();
+        let value = unsafe {
+            match self.format.bytes_per_pixel() {
+                4 => ptr::read_volatile(address as *const u32),
+                3 => {
+                    let b = ptr::read_volatile(address as *const u8) as u32;
+                    let g = ptr::read_volatile((address + 1) as *const u8) as u32;
+                    let r = ptr::read_volatile((address + 2) as *const u8) as u32;
+                    r << 16 | g << 8 | b
+                }
+                2 => ptr::read_volatile(address as *const u16) as u32,
+                _ => ptr::read_volatile(address as *const u8) as u32,
+            }
+        };
+        self.format.decode(value)
+    }
+}
+
+/// Composites the enabled layers the way the LTDC does and writes them as a
+/// binary PPM, one row per call of `write`. `panel_formats` are the formats
+/// the renderers of layer 1 and 2 write with, their corrections are reverted
+/// before blending so the image shows the colors that were meant. Stops and
+/// returns false if `write` fails.
+pub fn write_ppm<F: FnMut(&[u8]) -> bool>(
+    width: usize,
+    height: usize,
+    panel_formats: &[PanelFormat; 2],
+    mut write: F,
+) -> bool {
+    let layers: Vec<(Layer, &PanelFormat)> = LTDC_LAYERS
+        .iter()
+        .zip(panel_formats.iter())
+        .filter_map(|(&base, panel_format)| Layer::read(base).map(|layer| (layer, panel_format)))
+        .collect();
+    let background = unsafe { read_register(LTDC_BCCR) } & 0x00FF_FFFF;
+
+    let mut header = String::from("P6\n");
+    header.push_str(&width.to_string());
+    header.push(' ');
+    header.push_str(&height.to_string());
+    header.push_str("\n255\n");
+    if !write(header.as_bytes()) {
+        return false;
+    }
+
+    let mut row = Vec::with_capacity(width * 3);
+    let mut pixels = Vec::with_capacity(layers.len());
+    for y in 0..height {
+        row.clear();
+        for x in 0..width {
+            pixels.clear();
+            for &(ref layer, panel_format) in layers.iter() {
+                pixels.push((from_panel(panel_format, layer.pixel(x, y)), layer.constant_alpha));
+            }
+            let value = composite::composite(background, &pixels);
+            row.push((value >> 16) as u8);
+            row.push((value >> 8) as u8);
+            row.push(value as u8);
+        }
+        if !write(&row) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Reverts the corrections of `panel_format` on an ARGB8888 value, the alpha
+/// channel is kept.
+fn from_panel(panel_format: &PanelFormat, value: u32) -> u32 {
+    let color = panel_format.from_panel(Color::rgb((value >> 16) as u8, (value >> 8) as u8, value as u8));
+    value & 0xFF00_0000 | (color.red as u32) << 16 | (color.green as u32) << 8 | color.blue as u32
+}
+
+/// Saves a screenshot on the host over semihosting, so gdb has to be attached.
+pub fn save(path: &str, width: usize, height: usize, panel_formats: &[PanelFormat; 2]) -> bool {
+    match File::create(path) {
+        Some(mut file) => write_ppm(width, height, panel_formats, |data| file.write(data)),
+        None => false,
+    }
+}
+
+/// Saves `screenshot-<time>.ppm` in the working directory of gdb, returns
+/// false if that failed. Called by the `screenshot` command of `.gdbinit`.
+#[no_mangle]
+pub extern "C" fn take_screenshot() -> bool {
+    // without a debugger the semihosting calls would fault
+    if !semihosting::is_debugger_attached() {
+        return false;
+    }
+    let mut path = String::from("screenshot-");
+    path.push_str(&semihosting::time().to_string());
+    path.push_str(".ppm");
+    let (width, height) = panel::size();
+    let result = save(
+        &path,
+        width as usize,
+        height as usize,
+        // only the bottom layer corrects the swapped data lines
+        &[
+            PanelFormat::discovery(PixelFormat::Argb8888),
+            PanelFormat::plain(PixelFormat::Argb8888),
+        ],
+    );
+    if result {
+        info!("saved {}", path);
+    } else {
+        warn!("could not save {}", path);
+    }
+    result
+}
+
+unsafe fn read_register(address: usize) -> u32 {
+    ptr::read_volatile(address as *const u32)
+}
@@ -0,0 +1,86 @@
+#![allow(dead_code)]
+
+use core::ptr;
+
+use panel;
+
+pub const SDRAM_START: usize = 0xC000_0000;
+pub const SDRAM_SIZE: usize = 8 * 1024 * 1024;
+
+// The LCD driver places its layers at the start of the SDRAM, layer 1 with
+// 4 and layer 2 with 2 bytes per pixel
+pub const LAYER_1_ADDRESS: usize = SDRAM_START;
+const LAYER_1_BYTES_PER_PIXEL: usize = 4;
+const LAYER_2_BYTES_PER_PIXEL: usize = 2;
+
+// zero until the first allocation, which places the buffers behind the layers
+static mut NEXT_FREE: usize = 0;
+
+pub fn layer_1_size() -> usize {
+    let (width, height) = panel::size();
+    (width * height) as usize * LAYER_1_BYTES_PER_PIXEL
+}
+
+pub fn layer_2_address() -> usize {
+    LAYER_1_ADDRESS + layer_1_size()
+}
+
+pub fn layer_2_size() -> usize {
+    let (width, height) = panel::size();
+    (width * height) as usize * LAYER_2_BYTES_PER_PIXEL
+}
+
+/// A block of 32 bit words in the SDRAM behind the LCD layers.
+///
+/// Buffers are taken from a simple bump allocator and are never freed, so they
+/// should be allocated once at startup, after `lcd::init`.
+pub struct SdramBuffer {
+    address: usize,
+    len: usize,
+}
+
+impl SdramBuffer {
+    pub fn allocate(words: usize) -> Option<SdramBuffer> {
+        unsafe {
+            if NEXT_FREE == 0 {
+                NEXT_FREE = layer_2_address() + layer_2_size();
+            }
+            let address = NEXT_FREE;
+            let end = address + words * 4;
+            if end > SDRAM_START + SDRAM_SIZE {
+                return None;
+            }
+            NEXT_FREE = end;
+            Some(SdramBuffer {
+                address: address,
+                len: words,
+            })
+        }
+    }
+
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn as_ptr(&self) -> *const u32 {
+        self.address as *const u32
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut u32 {
+        self.address as *mut u32
+    }
+
+    pub fn read(&self, index: usize) -> u32 {
+        assert!(index < self.len);
+        unsafe { ptr::read_volatile(self.as_ptr().offset(index as isize)) }
+    }
+
+    pub fn write(&mut self, index: usize, value: u32) {
+        assert!(index < self.len);
+        unsafe { ptr::write_volatile(self.as_mut_ptr().offset(index as isize), value) }
+    }
+}
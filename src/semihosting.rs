@@ -0,0 +1,140 @@
+#![allow(dead_code)]
+
+extern crate alloc;
+
+use alloc::Vec;
+use core::ptr;
+
+// Semihosting calls are breakpoints with the immediate 0xAB, which are
+// handled by `semihosting.py` in gdb. Without an attached debugger the
+// breakpoint escalates to a HardFault, so only use this while debugging.
+
+const SYS_OPEN: usize = 0x01;
+const SYS_CLOSE: usize = 0x02;
+const SYS_WRITE: usize = 0x05;
+const SYS_READ: usize = 0x06;
+const SYS_CLOCK: usize = 0x10;
+const SYS_TIME: usize = 0x11;
+const SYS_EXIT: usize = 0x18;
+const SYS_EXIT_EXTENDED: usize = 0x20;
+
+// reason of SYS_EXIT for a regular exit of the application
+const ADP_STOPPED_APPLICATION_EXIT: usize = 0x20026;
+
+const DHCSR: usize = 0xE000_EDF0;
+
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+/// The binary modes of `fopen`, numbered as in the semihosting specification.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    Read = 1,
+    ReadWrite = 3,
+    Write = 5,
+    WriteRead = 7,
+    Append = 9,
+    AppendRead = 11,
+}
+
+/// Returns true if a debugger is connected, so semihosting calls don't fault.
+pub fn is_debugger_attached() -> bool {
+    // C_DEBUGEN of the debug halting control and status register
+    unsafe { ptr::read_volatile(DHCSR as *const u32) & 1 != 0 }
+}
+
+/// Executes semihosting call `operation` with the argument block at `args`.
+pub unsafe fn call(operation: usize, args: *const usize) -> usize {
+    let result;
+    asm!("bkpt 0xAB"
+         : "={r0}"(result)
+         : "{r0}"(operation), "{r1}"(args)
+         : "memory"
+         : "volatile");
+    result
+}
+
+/// Writes `data` to a file descriptor of the host. Returns the number of
+/// bytes that were not written.
+pub fn write(fd: usize, data: &[u8]) -> usize {
+    let args = [fd, data.as_ptr() as usize, data.len()];
+    unsafe { call(SYS_WRITE, args.as_ptr()) }
+}
+
+/// Reads into `buffer` from a file descriptor of the host. Returns the number
+/// of bytes that were not read, so `buffer.len()` means end of file.
+pub fn read(fd: usize, buffer: &mut [u8]) -> usize {
+    let args = [fd, buffer.as_mut_ptr() as usize, buffer.len()];
+    unsafe { call(SYS_READ, args.as_ptr()) }
+}
+
+/// Centiseconds since the debugger started, if the host supports it.
+pub fn clock() -> Option<usize> {
+    let result = unsafe { call(SYS_CLOCK, 0 as *const usize) };
+    if result as isize == -1 {
+        None
+    } else {
+        Some(result)
+    }
+}
+
+/// Seconds since 1970-01-01 on the host.
+pub fn time() -> usize {
+    unsafe { call(SYS_TIME, 0 as *const usize) }
+}
+
+/// Ends the debug session, gdb exits with `code`.
+pub fn exit(code: usize) -> ! {
+    let args = [ADP_STOPPED_APPLICATION_EXIT, code];
+    unsafe {
+        call(SYS_EXIT_EXTENDED, args.as_ptr());
+        // hosts without the extended call only know success
+        call(SYS_EXIT, ADP_STOPPED_APPLICATION_EXIT as *const usize);
+    }
+    loop {}
+}
+
+/// A file on the host, which is closed when dropped.
+pub struct File {
+    fd: usize,
+}
+
+impl File {
+    /// Opens `path`, which is relative to the working directory of gdb.
+    pub fn open(path: &str, mode: OpenMode) -> Option<File> {
+        // the host expects a null terminated string
+        let mut name = Vec::with_capacity(path.len() + 1);
+        name.extend_from_slice(path.as_bytes());
+        name.push(0);
+        let args = [name.as_ptr() as usize, mode as usize, path.len()];
+        let fd = unsafe { call(SYS_OPEN, args.as_ptr()) };
+        if fd as isize == -1 {
+            None
+        } else {
+            Some(File { fd: fd })
+        }
+    }
+
+    pub fn create(path: &str) -> Option<File> {
+        File::open(path, OpenMode::Write)
+    }
+
+    /// Writes all of `data`, returns false if the host could not write it.
+    pub fn write(&mut self, data: &[u8]) -> bool {
+        write(self.fd, data) == 0
+    }
+
+    /// Returns the number of bytes read, 0 at the end of the file.
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        buffer.len() - read(self.fd, buffer)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let args = [self.fd];
+        unsafe {
+            call(SYS_CLOSE, args.as_ptr());
+        }
+    }
+}
@@ -0,0 +1,174 @@
+#![allow(dead_code)]
+
+extern crate stm32f7_discovery as stm32f7;
+
+use stm32f7::lcd::Color;
+
+use background::BackgroundKind;
+use lighting::{FaceColors, Light};
+use renderer::hsv_color;
+
+pub const THEME_COUNT: usize = 5;
+
+#[derive(Clone, Copy)]
+pub struct BackgroundStyle {
+    pub base_color: Color,
+    pub accent_saturation: f32,
+    pub accent_value: f32,
+    pub kind: BackgroundKind,
+}
+
+#[derive(Clone, Copy)]
+pub struct Theme {
+    pub name: &'static str,
+    pub hue_step: i32,
+    pub saturation: f32,
+    pub value: f32,
+    pub light: Light,
+    pub outline_color: Option<Color>,
+    pub background: BackgroundStyle,
+    pub hud_color: Color,
+}
+
+impl Theme {
+    pub fn builtin(index: usize) -> Theme {
+        match index % THEME_COUNT {
+            0 => Theme::classic(),
+            1 => Theme::pastel(),
+            2 => Theme::neon(),
+            3 => Theme::monochrome(),
+            _ => Theme::high_contrast(),
+        }
+    }
+
+    pub fn classic() -> Theme {
+        Theme {
+            name: "Classic",
+            hue_step: 10,
+            saturation: 0.5f32,
+            value: 1f32,
+            light: Light::new((0.5f32, -0.75f32, -1f32), 0.35f32, 0.9f32),
+            outline_color: None,
+            background: BackgroundStyle {
+                base_color: Color::rgb(0, 0, 64),
+                accent_saturation: 1f32,
+                accent_value: 0.25f32,
+                kind: BackgroundKind::PerRound,
+            },
+            hud_color: Color::from_hex(0xffffff),
+        }
+    }
+
+    pub fn pastel() -> Theme {
+        Theme {
+            name: "Pastel",
+            hue_step: 15,
+            saturation: 0.3f32,
+            value: 1f32,
+            light: Light {
+                direction: (0.5f32, -1f32, -1f32),
+                ambient: 0.55f32,
+                diffuse: 0.6f32,
+                gradient: 0.1f32,
+                depth_darkening: 0.2f32,
+                depth_layers: 10,
+            },
+            outline_color: None,
+            background: BackgroundStyle {
+                base_color: Color::rgb(96, 64, 112),
+                accent_saturation: 0.4f32,
+                accent_value: 0.6f32,
+                kind: BackgroundKind::Clouds,
+            },
+            hud_color: Color::from_hex(0xffffff),
+        }
+    }
+
+    pub fn neon() -> Theme {
+        Theme {
+            name: "Neon",
+            hue_step: 30,
+            saturation: 1f32,
+            value: 1f32,
+            light: Light {
+                direction: (0.25f32, -0.5f32, -1f32),
+                ambient: 0.2f32,
+                diffuse: 1f32,
+                gradient: 0.4f32,
+                depth_darkening: 0.5f32,
+                depth_layers: 15,
+            },
+            outline_color: Some(Color::from_hex(0xffffff)),
+            background: BackgroundStyle {
+                base_color: Color::rgb(0, 0, 0),
+                accent_saturation: 1f32,
+                accent_value: 0.15f32,
+                kind: BackgroundKind::Grid,
+            },
+            hud_color: Color::from_hex(0x00ffff),
+        }
+    }
+
+    pub fn monochrome() -> Theme {
+        Theme {
+            name: "Monochrome",
+            hue_step: 0,
+            saturation: 0f32,
+            value: 0.9f32,
+            light: Light {
+                direction: (0.5f32, -0.75f32, -1f32),
+                ambient: 0.25f32,
+                diffuse: 0.9f32,
+                gradient: 0.2f32,
+                depth_darkening: 0.3f32,
+                depth_layers: 10,
+            },
+            outline_color: Some(Color::from_hex(0x000000)),
+            background: BackgroundStyle {
+                base_color: Color::rgb(32, 32, 32),
+                accent_saturation: 0f32,
+                accent_value: 0.1f32,
+                kind: BackgroundKind::Skyline,
+            },
+            hud_color: Color::from_hex(0xffffff),
+        }
+    }
+
+    pub fn high_contrast() -> Theme {
+        Theme {
+            name: "High Contrast",
+            hue_step: 60,
+            saturation: 1f32,
+            value: 1f32,
+            light: Light::new((0.25f32, -0.75f32, -1f32), 0.1f32, 1f32),
+            outline_color: Some(Color::from_hex(0x000000)),
+            background: BackgroundStyle {
+                base_color: Color::rgb(0, 0, 0),
+                accent_saturation: 0f32,
+                accent_value: 0f32,
+                kind: BackgroundKind::Gradient,
+            },
+            hud_color: Color::from_hex(0xffff00),
+        }
+    }
+
+    pub fn next_hue(&self, hue: f32) -> f32 {
+        ((hue as i32 + self.hue_step) % 360) as f32
+    }
+
+    pub fn block_color(&self, hue: f32) -> Color {
+        hsv_color(hue, self.saturation, self.value)
+    }
+
+    pub fn face_colors(&self, hue: f32, layer: usize) -> FaceColors {
+        self.light.shade(self.block_color(hue), layer)
+    }
+
+    pub fn accent_color(&self, hue: f32) -> Color {
+        hsv_color(
+            hue,
+            self.background.accent_saturation,
+            self.background.accent_value,
+        )
+    }
+}
@@ -0,0 +1,246 @@
+#![allow(dead_code)]
+
+extern crate stm32f7_discovery as stm32f7;
+extern crate alloc;
+
+use alloc::Vec;
+use core::mem;
+use stm32f7::{i2c, system_clock, touch};
+
+use i2c_recovery;
+use touch_interrupt;
+
+// I2C errors are retried this often within one poll
+const RETRIES: usize = 3;
+// consecutive failed polls after which the controller counts as dead
+const MAX_FAILED_POLLS: u32 = 10;
+// how often a dead controller is probed again
+const PROBE_INTERVAL_MS: usize = 1000;
+// the controller sometimes reports no touch for a single poll while a finger
+// is down, so a release has to last this many polls
+const RELEASE_POLLS: u32 = 2;
+// in trigger mode the controller reports at every scan while it is touched,
+// so no report for this long means the lift was missed
+const RELEASE_TIMEOUT_MS: usize = 100;
+// new touches closer than this to a touch of the last report are taken for
+// moved fingers
+const SAME_TOUCH_DISTANCE: i32 = 40;
+
+/// A touch position in panel coordinates.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TouchPoint {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// A finger put on the screen and when the controller reported it, in ticks
+/// of the system clock.
+#[derive(Clone, Copy)]
+pub struct TouchDown {
+    pub position: TouchPoint,
+    pub ms: usize,
+}
+
+/// Reads the touch controller without panicking on I2C errors. A controller
+/// that stops responding is probed again from time to time.
+pub struct TouchInput {
+    i2c: i2c::I2C,
+    available: bool,
+    failed_polls: u32,
+    last_probe_ms: usize,
+    touches: Vec<TouchPoint>,
+    empty_polls: u32,
+    interrupt_mode: bool,
+    last_report: usize,
+    seen_errors: usize,
+    report_ms: usize,
+    touch_downs: Vec<TouchDown>,
+    read_touch_downs: usize,
+}
+
+impl TouchInput {
+    pub fn new(i2c: i2c::I2C) -> TouchInput {
+        let mut input = TouchInput {
+            i2c: i2c,
+            available: false,
+            failed_polls: 0,
+            last_probe_ms: system_clock::ticks(),
+            touches: Vec::new(),
+            empty_polls: 0,
+            interrupt_mode: false,
+            last_report: 0,
+            seen_errors: 0,
+            report_ms: 0,
+            touch_downs: Vec::new(),
+            read_touch_downs: 0,
+        };
+        input.available = input.probe();
+        if !input.available {
+            warn!("touch controller not found");
+        }
+        input
+    }
+
+    /// Returns false while the controller does not respond.
+    pub fn is_touch_available(&self) -> bool {
+        self.available
+    }
+
+    /// Lets the controller signal new touches on its INT pin, so `poll` only
+    /// picks up the report that was read in the background. The interrupts of
+    /// `touch_interrupt` have to be registered before.
+    pub fn enable_interrupt(&mut self) -> bool {
+        if !self.available {
+            return false;
+        }
+        self.interrupt_mode = touch_interrupt::init();
+        if self.interrupt_mode {
+            info!("reading the touch controller on interrupts");
+        } else {
+            warn!("touch controller interrupt not available, polling it");
+        }
+        self.seen_errors = touch_interrupt::errors();
+        self.interrupt_mode
+    }
+
+    /// The fingers put on the screen since the last call. With the interrupt
+    /// enabled these include touches that were lifted again before `poll`.
+    pub fn take_touch_downs(&mut self) -> Vec<TouchDown> {
+        mem::replace(&mut self.touch_downs, Vec::new())
+    }
+
+    /// The current touches, none while the controller does not respond.
+    pub fn poll(&mut self) -> Vec<TouchPoint> {
+        let ms = system_clock::ticks();
+        if self.available && self.interrupt_mode {
+            touch_interrupt::take_touch_downs(&mut self.read_touch_downs, &mut self.touch_downs);
+            // taken even if a transfer failed, so the blocking read below does
+            // not report the queued touches again
+            let report = touch_interrupt::take_report(&mut self.last_report);
+            if let Some(report) = report {
+                self.touches = report.touches[..report.count].iter().cloned().collect();
+                self.report_ms = report.ms;
+            }
+            let errors = touch_interrupt::errors();
+            if errors == self.seen_errors {
+                if report.is_none() && !self.touches.is_empty() && ms - self.report_ms > RELEASE_TIMEOUT_MS {
+                    self.touches.clear();
+                }
+                return self.touches.clone();
+            }
+            // a transfer in the background failed, the blocking read below
+            // retries it and keeps track of a dead controller
+            self.seen_errors = errors;
+        }
+
+        if !self.available && ms - self.last_probe_ms >= PROBE_INTERVAL_MS {
+            self.last_probe_ms = ms;
+            self.recover_bus();
+            if self.probe() {
+                info!("touch controller is back");
+                self.available = true;
+                self.failed_polls = 0;
+            }
+        }
+
+        if !self.available {
+            return Vec::new();
+        }
+
+        match self.read_touches() {
+            Some(touches) => {
+                self.failed_polls = 0;
+                self.report_ms = ms;
+                if touches.is_empty() && !self.touches.is_empty() {
+                    self.empty_polls += 1;
+                    if self.empty_polls < RELEASE_POLLS {
+                        return self.touches.clone();
+                    }
+                }
+                self.empty_polls = 0;
+                let touch_downs = &mut self.touch_downs;
+                new_touches(&self.touches, &touches, |position| {
+                    touch_downs.push(TouchDown {
+                        position: position,
+                        ms: ms,
+                    })
+                });
+                self.touches = touches;
+            }
+            None => {
+                self.failed_polls += 1;
+                if self.failed_polls >= MAX_FAILED_POLLS {
+                    error!("touch controller stopped responding");
+                    self.available = false;
+                    self.last_probe_ms = ms;
+                    self.touches.clear();
+                }
+            }
+        }
+        self.touches.clone()
+    }
+
+    /// Frees the bus in case the controller holds SDA low and resets I2C3,
+    /// which does not notice a stuck bus by itself.
+    fn recover_bus(&mut self) {
+        touch_interrupt::pause();
+        i2c_recovery::recover_i2c3();
+        i2c_recovery::restore_i2c3_pins();
+        touch_interrupt::reset_peripheral();
+        touch_interrupt::resume();
+    }
+
+    fn probe(&mut self) -> bool {
+        touch_interrupt::pause();
+        let found = (0..RETRIES).any(|_| touch::check_family_id(&mut self.i2c).is_ok());
+        touch_interrupt::resume();
+        found
+    }
+
+    fn read_touches(&mut self) -> Option<Vec<TouchPoint>> {
+        touch_interrupt::pause();
+        let mut result = None;
+        for _ in 0..RETRIES {
+            if let Ok(touches) = touch::touches(&mut self.i2c) {
+                result = Some(
+                    touches
+                        .iter()
+                        .map(|t| TouchPoint {
+                            x: t.x as i32,
+                            y: t.y as i32,
+                        })
+                        .collect(),
+                );
+                break;
+            }
+        }
+        touch_interrupt::resume();
+        result
+    }
+}
+
+/// Calls `found` for the touches that were added between two reports. The
+/// controller does not keep the order of the touches, so the ones furthest
+/// away from all previous touches are taken as the new ones. Does not
+/// allocate, so it is used in the touch interrupt as well.
+pub fn new_touches<F: FnMut(TouchPoint)>(previous: &[TouchPoint], current: &[TouchPoint], mut found: F) {
+    if current.len() <= previous.len() {
+        return;
+    }
+    let count = current.len().min(touch_interrupt::MAX_TOUCHES);
+    let mut candidates = [(0, TouchPoint { x: 0, y: 0 }); touch_interrupt::MAX_TOUCHES];
+    for (candidate, &t) in candidates.iter_mut().zip(current.iter()) {
+        let distance = previous
+            .iter()
+            .map(|p| (p.x - t.x).abs() + (p.y - t.y).abs())
+            .min()
+            .unwrap_or(i32::max_value());
+        *candidate = (distance, t);
+    }
+    candidates[..count].sort_unstable_by(|a, b| b.0.cmp(&a.0));
+    for &(distance, t) in candidates[..count].iter().take(current.len() - previous.len()) {
+        if distance > SAME_TOUCH_DISTANCE {
+            found(t);
+        }
+    }
+}
@@ -0,0 +1,400 @@
+#![allow(dead_code)]
+
+extern crate stm32f7_discovery as stm32f7;
+extern crate alloc;
+
+use alloc::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use stm32f7::system_clock;
+
+use input::{self, ButtonPin, Edges, Port};
+use touch_input::{self, TouchDown, TouchPoint};
+
+// Reads the FT5336 when it signals new data on its INT pin instead of polling
+// it every frame. The falling edge on PI13 starts an I2C transfer that runs
+// in the I2C3 interrupts, the result is picked up by `take_report`. Touches
+// that are put down are queued, so they are not lost if the finger is lifted
+// before the next poll.
+
+/// The INT pin of the controller, which is pulled low while it signals.
+pub const INT_PIN: ButtonPin = ButtonPin {
+    port: Port::I,
+    pin: 13,
+    active_low: true,
+};
+
+const I2C3_BASE: usize = 0x4000_5C00;
+const I2C_CR1: usize = I2C3_BASE + 0x00;
+const I2C_CR2: usize = I2C3_BASE + 0x04;
+const I2C_ISR: usize = I2C3_BASE + 0x18;
+const I2C_ICR: usize = I2C3_BASE + 0x1C;
+const I2C_RXDR: usize = I2C3_BASE + 0x24;
+const I2C_TXDR: usize = I2C3_BASE + 0x28;
+
+const CR1_PE: u32 = 1 << 0;
+// TXIE, RXIE, NACKIE, STOPIE, TCIE and ERRIE
+const CR1_INTERRUPTS: u32 = 0b1111_0110;
+
+const CR2_RD_WRN: u32 = 1 << 10;
+const CR2_START: u32 = 1 << 13;
+const CR2_STOP: u32 = 1 << 14;
+const CR2_NBYTES_SHIFT: u32 = 16;
+const CR2_AUTOEND: u32 = 1 << 25;
+
+const ISR_TXIS: u32 = 1 << 1;
+const ISR_RXNE: u32 = 1 << 2;
+const ISR_NACKF: u32 = 1 << 4;
+const ISR_STOPF: u32 = 1 << 5;
+const ISR_TC: u32 = 1 << 6;
+const ISR_ERRORS: u32 = 0b111 << 8;
+
+const FT5336_ADDRESS: u32 = 0x38;
+const TD_STATUS: u8 = 0x02;
+// trigger mode pulses INT for every new report
+const G_MODE: u8 = 0xA4;
+const G_MODE_TRIGGER: u8 = 0x01;
+
+pub const MAX_TOUCHES: usize = 5;
+// TD_STATUS and 6 bytes for every touch
+const REPORT_LENGTH: usize = 1 + 6 * MAX_TOUCHES;
+// the event flag of a touch that was lifted
+const EVENT_PUT_UP: u8 = 0b01;
+
+// how long a blocking transfer or a running transfer may take
+const TIMEOUT_MS: usize = 10;
+// touch-downs that are not picked up in time are dropped, the oldest first
+const QUEUE_LENGTH: usize = 8;
+
+const IDLE: usize = 0;
+const SEND_REGISTER: usize = 1;
+const RECEIVE: usize = 2;
+const STOPPING: usize = 3;
+
+/// The touches of one report of the controller and when it signalled it.
+#[derive(Clone, Copy)]
+pub struct Report {
+    pub touches: [TouchPoint; MAX_TOUCHES],
+    pub count: usize,
+    pub ms: usize,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STATE: AtomicUsize = AtomicUsize::new(IDLE);
+// another edge came while a transfer was running
+static PENDING: AtomicBool = AtomicBool::new(false);
+static FAILED: AtomicBool = AtomicBool::new(false);
+static EDGE_MS: AtomicUsize = AtomicUsize::new(0);
+static TRANSFER_MS: AtomicUsize = AtomicUsize::new(0);
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+static REPORTS: AtomicUsize = AtomicUsize::new(0);
+
+static mut BUFFER: [u8; REPORT_LENGTH] = [0; REPORT_LENGTH];
+static mut REPORT: Report = Report {
+    touches: [TouchPoint { x: 0, y: 0 }; MAX_TOUCHES],
+    count: 0,
+    ms: 0,
+};
+// only written in the interrupt, `TOUCH_DOWNS_WRITTEN` counts all entries ever written
+static TOUCH_DOWNS_WRITTEN: AtomicUsize = AtomicUsize::new(0);
+static mut TOUCH_DOWNS: [TouchDown; QUEUE_LENGTH] = [TouchDown {
+    position: TouchPoint { x: 0, y: 0 },
+    ms: 0,
+}; QUEUE_LENGTH];
+
+/// Switches the controller to trigger mode and enables the external
+/// interrupt of the INT pin and the I2C3 interrupts. `handle_exti`,
+/// `handle_event` and `handle_error` have to be registered before. Returns
+/// false if the controller did not accept the mode.
+pub fn init() -> bool {
+    unsafe {
+        if !write_controller_register(G_MODE, G_MODE_TRIGGER) {
+            return false;
+        }
+
+        input::init_interrupt_pin(&INT_PIN, Edges::Falling);
+    }
+    ENABLED.store(true, Ordering::SeqCst);
+    resume();
+    true
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// The number of transfers that failed since the start.
+pub fn errors() -> usize {
+    ERRORS.load(Ordering::SeqCst)
+}
+
+/// The last report, if there is a new one since the last call.
+pub fn take_report(last: &mut usize) -> Option<Report> {
+    let reports = REPORTS.load(Ordering::SeqCst);
+    if reports == *last {
+        return None;
+    }
+    *last = reports;
+    let primask = disable_interrupts();
+    let report = unsafe { ptr::read_volatile(&REPORT) };
+    restore_interrupts(primask);
+    Some(report)
+}
+
+/// Appends the touch-downs since the last call to `touch_downs`. `read` counts
+/// the ones that were taken before.
+pub fn take_touch_downs(read: &mut usize, touch_downs: &mut Vec<TouchDown>) {
+    let primask = disable_interrupts();
+    let written = TOUCH_DOWNS_WRITTEN.load(Ordering::SeqCst);
+    if written - *read > QUEUE_LENGTH {
+        *read = written - QUEUE_LENGTH;
+    }
+    while *read != written {
+        touch_downs.push(unsafe { TOUCH_DOWNS[*read % QUEUE_LENGTH] });
+        *read += 1;
+    }
+    restore_interrupts(primask);
+}
+
+/// Stops starting transfers and waits for the running one, so the blocking
+/// driver can use the bus.
+pub fn pause() {
+    if !is_enabled() {
+        return;
+    }
+    input::set_line_enabled(&INT_PIN, false);
+    let start = system_clock::ticks();
+    while STATE.load(Ordering::SeqCst) != IDLE {
+        if system_clock::ticks() - start > TIMEOUT_MS {
+            reset_peripheral();
+            STATE.store(IDLE, Ordering::SeqCst);
+            break;
+        }
+    }
+    PENDING.store(false, Ordering::SeqCst);
+    unsafe {
+        let cr1 = read_register(I2C_CR1);
+        write_register(I2C_CR1, cr1 & !CR1_INTERRUPTS);
+    }
+}
+
+pub fn resume() {
+    if !is_enabled() {
+        return;
+    }
+    unsafe {
+        let cr1 = read_register(I2C_CR1);
+        write_register(I2C_CR1, cr1 | CR1_INTERRUPTS);
+    }
+    // edges from while the line was masked are dropped
+    input::take_pending(&INT_PIN);
+    input::set_line_enabled(&INT_PIN, true);
+}
+
+/// Handles the falling edge of the INT pin. Shares its interrupt with lines
+/// 10 to 15, so only the pending bit of `INT_PIN` is cleared.
+pub fn handle_exti() {
+    if !input::take_pending(&INT_PIN) {
+        return;
+    }
+    EDGE_MS.store(system_clock::ticks(), Ordering::SeqCst);
+    if STATE.load(Ordering::SeqCst) == IDLE {
+        start_transfer();
+    } else {
+        PENDING.store(true, Ordering::SeqCst);
+    }
+}
+
+pub fn handle_event() {
+    let state = STATE.load(Ordering::SeqCst);
+    unsafe {
+        let isr = read_register(I2C_ISR);
+        if isr & ISR_NACKF != 0 {
+            write_register(I2C_ICR, ISR_NACKF);
+            FAILED.store(true, Ordering::SeqCst);
+            if state == SEND_REGISTER {
+                // without AUTOEND the stop condition has to be sent here
+                let cr2 = read_register(I2C_CR2);
+                write_register(I2C_CR2, cr2 | CR2_STOP);
+            }
+            STATE.store(STOPPING, Ordering::SeqCst);
+        }
+        if isr & ISR_TXIS != 0 {
+            write_register(I2C_TXDR, TD_STATUS as u32);
+        }
+        if isr & ISR_TC != 0 && state == SEND_REGISTER && !FAILED.load(Ordering::SeqCst) {
+            // repeated start for reading the report
+            RECEIVED.store(0, Ordering::SeqCst);
+            STATE.store(RECEIVE, Ordering::SeqCst);
+            write_register(
+                I2C_CR2,
+                FT5336_ADDRESS << 1 | CR2_RD_WRN | (REPORT_LENGTH as u32) << CR2_NBYTES_SHIFT
+                    | CR2_AUTOEND | CR2_START,
+            );
+        }
+        if isr & ISR_RXNE != 0 {
+            let byte = read_register(I2C_RXDR) as u8;
+            let received = RECEIVED.load(Ordering::SeqCst);
+            if received < REPORT_LENGTH {
+                BUFFER[received] = byte;
+                RECEIVED.store(received + 1, Ordering::SeqCst);
+            }
+        }
+        if isr & ISR_STOPF != 0 {
+            write_register(I2C_ICR, ISR_STOPF);
+            finish_transfer();
+        }
+    }
+}
+
+pub fn handle_error() {
+    unsafe {
+        let isr = read_register(I2C_ISR);
+        write_register(I2C_ICR, isr & ISR_ERRORS);
+    }
+    // a bus error or a lost arbitration leave the peripheral in an unknown state
+    reset_peripheral();
+    FAILED.store(true, Ordering::SeqCst);
+    finish_transfer();
+}
+
+fn start_transfer() {
+    FAILED.store(false, Ordering::SeqCst);
+    TRANSFER_MS.store(EDGE_MS.load(Ordering::SeqCst), Ordering::SeqCst);
+    STATE.store(SEND_REGISTER, Ordering::SeqCst);
+    unsafe {
+        write_register(I2C_CR2, FT5336_ADDRESS << 1 | 1 << CR2_NBYTES_SHIFT | CR2_START);
+    }
+}
+
+fn finish_transfer() {
+    let complete = RECEIVED.load(Ordering::SeqCst) == REPORT_LENGTH;
+    if FAILED.load(Ordering::SeqCst) || !complete {
+        ERRORS.fetch_add(1, Ordering::SeqCst);
+    } else {
+        unsafe {
+            let report = parse_report(&BUFFER, TRANSFER_MS.load(Ordering::SeqCst));
+            // compared with every report, so a touch that is lifted before
+            // the next poll still counts, with the time of its first report
+            touch_input::new_touches(&REPORT.touches[..REPORT.count], &report.touches[..report.count], |position| {
+                push_touch_down(TouchDown {
+                    position: position,
+                    ms: report.ms,
+                })
+            });
+            REPORT = report;
+        }
+        REPORTS.fetch_add(1, Ordering::SeqCst);
+    }
+    RECEIVED.store(0, Ordering::SeqCst);
+    STATE.store(IDLE, Ordering::SeqCst);
+    if PENDING.swap(false, Ordering::SeqCst) {
+        start_transfer();
+    }
+}
+
+fn push_touch_down(touch_down: TouchDown) {
+    let written = TOUCH_DOWNS_WRITTEN.load(Ordering::SeqCst);
+    unsafe {
+        TOUCH_DOWNS[written % QUEUE_LENGTH] = touch_down;
+    }
+    TOUCH_DOWNS_WRITTEN.store(written + 1, Ordering::SeqCst);
+}
+
+fn parse_report(data: &[u8; REPORT_LENGTH], ms: usize) -> Report {
+    let mut report = Report {
+        touches: [TouchPoint { x: 0, y: 0 }; MAX_TOUCHES],
+        count: 0,
+        ms: ms,
+    };
+    let count = (data[0] & 0x0F) as usize;
+    // the controller reports garbage counts while it starts up
+    if count > MAX_TOUCHES {
+        return report;
+    }
+    for i in 0..count {
+        let touch = &data[1 + 6 * i..1 + 6 * (i + 1)];
+        if touch[0] >> 6 == EVENT_PUT_UP {
+            continue;
+        }
+        // the axes of the controller are swapped compared to the panel,
+        // like in `touch::touches`
+        report.touches[report.count] = TouchPoint {
+            x: ((touch[2] as i32 & 0x0F) << 8) | touch[3] as i32,
+            y: ((touch[0] as i32 & 0x0F) << 8) | touch[1] as i32,
+        };
+        report.count += 1;
+    }
+    report
+}
+
+/// Writes a register of the controller while the I2C interrupts are disabled.
+unsafe fn write_controller_register(register: u8, value: u8) -> bool {
+    write_register(I2C_CR2, FT5336_ADDRESS << 1 | 2 << CR2_NBYTES_SHIFT | CR2_AUTOEND | CR2_START);
+    for &byte in [register, value].iter() {
+        if !wait_for(ISR_TXIS) {
+            return false;
+        }
+        write_register(I2C_TXDR, byte as u32);
+    }
+    let stopped = wait_for(ISR_STOPF);
+    write_register(I2C_ICR, ISR_STOPF);
+    stopped
+}
+
+/// Waits until `flag` is set in ISR, fails on a NACK or after `TIMEOUT_MS`.
+unsafe fn wait_for(flag: u32) -> bool {
+    let start = system_clock::ticks();
+    loop {
+        let isr = read_register(I2C_ISR);
+        if isr & flag != 0 {
+            return true;
+        }
+        if isr & ISR_NACKF != 0 {
+            write_register(I2C_ICR, ISR_NACKF);
+            return false;
+        }
+        if system_clock::ticks() - start > TIMEOUT_MS {
+            reset_peripheral();
+            return false;
+        }
+    }
+}
+
+/// Clearing PE resets the state machine and the flags of the peripheral.
+pub fn reset_peripheral() {
+    unsafe {
+        let cr1 = read_register(I2C_CR1);
+        write_register(I2C_CR1, cr1 & !CR1_PE);
+        // PE has to stay low for three APB clock cycles
+        while read_register(I2C_CR1) & CR1_PE != 0 {}
+        write_register(I2C_CR1, cr1 | CR1_PE);
+    }
+}
+
+fn disable_interrupts() -> u32 {
+    let primask: u32;
+    unsafe {
+        asm!("mrs $0, PRIMASK
+              cpsid i"
+             : "=r"(primask) ::: "volatile");
+    }
+    primask
+}
+
+fn restore_interrupts(primask: u32) {
+    if primask & 1 == 0 {
+        unsafe {
+            asm!("cpsie i" :::: "volatile");
+        }
+    }
+}
+
+unsafe fn read_register(address: usize) -> u32 {
+    ptr::read_volatile(address as *const u32)
+}
+
+unsafe fn write_register(address: usize, value: u32) {
+    ptr::write_volatile(address as *mut u32, value);
+}
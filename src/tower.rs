@@ -0,0 +1,162 @@
+#![allow(dead_code)]
+
+extern crate stm32f7_discovery as stm32f7;
+extern crate alloc;
+
+use alloc::Vec;
+use core::mem;
+
+use block::Block;
+
+const BLOCK_HEIGHT: i32 = 15;
+// a block this close to the one below snaps onto it
+const FIT_DISTANCE: i32 = 3;
+// perfectly placed blocks in a row that make the next block grow
+const IN_A_ROW: usize = 5;
+const BONUS: i32 = 4;
+const MIN_SIZE: i32 = 4;
+
+/// The stacking state of one player: the placed blocks, the moving block and
+/// the camera position.
+pub struct Tower {
+    pub blocks: Vec<Block>,
+    pub current_block: Block,
+    pub base_x: i32,
+    pub base_y: i32,
+    pub hue: f32,
+    pub score: i32,
+    last_ms: usize,
+}
+
+impl Tower {
+    pub fn new(base_x: i32, base_y: i32, hue: f32, ms: usize) -> Tower {
+        let mut blocks = Vec::new();
+        blocks.push(Block::new(-50, -60, -50, 100, 60, 100, hue));
+        Tower {
+            blocks: blocks,
+            current_block: Block::new(-50, -60 - BLOCK_HEIGHT, -50, 100, BLOCK_HEIGHT, 100, hue),
+            base_x: base_x,
+            base_y: base_y,
+            hue: hue,
+            score: 0,
+            last_ms: ms,
+        }
+    }
+
+    /// Moves the current block back and forth along the x or z axis.
+    pub fn move_current(&mut self, ms: usize) {
+        let current_block = &mut self.current_block;
+        let last_block = self.blocks.last().unwrap();
+
+        let mut size = current_block.width;
+        if current_block.depth > size {
+            size = current_block.depth;
+        }
+        let p_time = 30 * size + 500;
+        let mut p = (ms.saturating_sub(self.last_ms) as i32 % p_time) as f32 / p_time as f32 * 2f32;
+        if p > 1f32 {
+            p = 2f32 - p;
+        }
+        p = -2f32 * p * p * p + 3f32 * p * p;
+
+        if self.score % 2 == 0 {
+            current_block.x = ((3f32 * current_block.width as f32 * (p - 0.5f32)) as i32
+                - current_block.width / 2 + last_block.x
+                + last_block.width / 2) / 2 * 2;
+        } else {
+            current_block.z = ((3f32 * current_block.depth as f32 * (p - 0.5f32)) as i32
+                - current_block.depth / 2 + last_block.z
+                + last_block.depth / 2) / 2 * 2;
+        }
+    }
+
+    /// Cuts off the part of the current block that does not lie on the tower.
+    /// Returns false if too little of it is left, which ends the game.
+    pub fn cut(&mut self) -> bool {
+        let current_block = &mut self.current_block;
+        let last_block = self.blocks.last().unwrap();
+
+        if abs(current_block.x - last_block.x) <= FIT_DISTANCE
+            && abs(current_block.z - last_block.z) <= FIT_DISTANCE
+        {
+            debug!("snapped onto the block below");
+            current_block.x = last_block.x;
+            current_block.z = last_block.z;
+
+            if self.blocks.len() >= IN_A_ROW {
+                let mut is_perfect = true;
+                for i in self.blocks.len() - IN_A_ROW + 1..self.blocks.len() {
+                    let lower_block = &self.blocks[i - 1];
+                    let b = &self.blocks[i];
+                    if b.x != lower_block.x || b.z != lower_block.z
+                        || b.width != lower_block.width
+                        || b.depth != lower_block.depth
+                    {
+                        is_perfect = false;
+                        break;
+                    }
+                }
+                if is_perfect {
+                    info!("{} perfect placements in a row, the block grows", IN_A_ROW);
+                    current_block.x -= BONUS;
+                    current_block.z -= BONUS;
+                    current_block.width += 2 * BONUS;
+                    current_block.depth += 2 * BONUS;
+                }
+            }
+        } else {
+            if current_block.x < last_block.x {
+                current_block.width -= last_block.x - current_block.x;
+                current_block.x = last_block.x;
+            }
+            if current_block.x + current_block.width > last_block.x + last_block.width {
+                current_block.width -=
+                    current_block.x + current_block.width - last_block.x - last_block.width;
+            }
+            if current_block.z < last_block.z {
+                current_block.depth -= last_block.z - current_block.z;
+                current_block.z = last_block.z;
+            }
+            if current_block.z + current_block.depth > last_block.z + last_block.depth {
+                current_block.depth -=
+                    current_block.z + current_block.depth - last_block.z - last_block.depth;
+            }
+            debug!("cut to {}x{}", current_block.width, current_block.depth);
+        }
+
+        current_block.width >= MIN_SIZE && current_block.depth >= MIN_SIZE
+    }
+
+    /// Moves the camera up once the current block reaches the upper third of a
+    /// view with the given height. Returns true if the tower has to be redrawn.
+    pub fn follow(&mut self, view_height: i32) -> bool {
+        if self.base_y + self.current_block.y < view_height / 3 {
+            self.base_y += view_height / 3;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Puts the current block onto the tower and starts the next one.
+    pub fn stack(&mut self, ms: usize, hue: f32) {
+        let next_block = {
+            let b = &self.current_block;
+            Block::new(b.x, b.y - BLOCK_HEIGHT, b.z, b.width, BLOCK_HEIGHT, b.depth, hue)
+        };
+        let placed = mem::replace(&mut self.current_block, next_block);
+        self.blocks.push(placed);
+
+        self.hue = hue;
+        self.last_ms = ms - (ms as i32 % 100) as usize;
+        self.score += 1;
+    }
+}
+
+fn abs(value: i32) -> i32 {
+    if value < 0 {
+        -value
+    } else {
+        value
+    }
+}
@@ -0,0 +1,75 @@
+#![allow(dead_code)]
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use panel;
+
+const LTDC_BASE: usize = 0x4001_6800;
+const LTDC_IER: usize = LTDC_BASE + 0x34;
+const LTDC_ISR: usize = LTDC_BASE + 0x38;
+const LTDC_ICR: usize = LTDC_BASE + 0x3C;
+const LTDC_LIPCR: usize = LTDC_BASE + 0x40;
+
+const LINE_INTERRUPT: u32 = 1 << 0;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static VSYNC_COUNT: AtomicUsize = AtomicUsize::new(0);
+static LAST_WAIT: AtomicUsize = AtomicUsize::new(0);
+static MISSED_VSYNCS: AtomicUsize = AtomicUsize::new(0);
+
+/// Enables the LTDC line interrupt at the start of the vertical blanking period.
+/// `handle_interrupt` has to be registered for the LTDC interrupt before and the
+/// LCD has to be initialized, the blanking period is taken from its timing.
+pub fn init() {
+    unsafe {
+        ptr::write_volatile(LTDC_LIPCR as *mut u32, panel::vblank_line());
+        ptr::write_volatile(LTDC_ICR as *mut u32, LINE_INTERRUPT);
+        let ier = ptr::read_volatile(LTDC_IER as *const u32);
+        ptr::write_volatile(LTDC_IER as *mut u32, ier | LINE_INTERRUPT);
+    }
+    LAST_WAIT.store(VSYNC_COUNT.load(Ordering::SeqCst), Ordering::SeqCst);
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn handle_interrupt() {
+    unsafe {
+        if ptr::read_volatile(LTDC_ISR as *const u32) & LINE_INTERRUPT != 0 {
+            ptr::write_volatile(LTDC_ICR as *mut u32, LINE_INTERRUPT);
+            VSYNC_COUNT.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Blocks until the next vertical blanking period starts.
+///
+/// Every blanking period that passed since the last call counts as missed,
+/// because the frame drawn in between could not be shown in time.
+pub fn wait_for_vsync() {
+    if !is_enabled() {
+        return;
+    }
+    let start = VSYNC_COUNT.load(Ordering::SeqCst);
+    let last = LAST_WAIT.load(Ordering::SeqCst);
+    if start > last {
+        MISSED_VSYNCS.fetch_add(start - last, Ordering::SeqCst);
+    }
+    while VSYNC_COUNT.load(Ordering::SeqCst) == start {}
+    LAST_WAIT.store(VSYNC_COUNT.load(Ordering::SeqCst), Ordering::SeqCst);
+}
+
+pub fn vsync_count() -> usize {
+    VSYNC_COUNT.load(Ordering::SeqCst)
+}
+
+pub fn missed_vsyncs() -> usize {
+    MISSED_VSYNCS.load(Ordering::SeqCst)
+}
+
+pub fn reset_statistics() {
+    MISSED_VSYNCS.store(0, Ordering::SeqCst);
+    LAST_WAIT.store(VSYNC_COUNT.load(Ordering::SeqCst), Ordering::SeqCst);
+}
@@ -0,0 +1,118 @@
+#![allow(dead_code)]
+
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const IWDG_BASE: usize = 0x4000_3000;
+const IWDG_KR: usize = IWDG_BASE + 0x00;
+const IWDG_PR: usize = IWDG_BASE + 0x04;
+const IWDG_RLR: usize = IWDG_BASE + 0x08;
+const IWDG_SR: usize = IWDG_BASE + 0x0C;
+
+const KEY_START: u32 = 0xCCCC;
+const KEY_ACCESS: u32 = 0x5555;
+const KEY_FEED: u32 = 0xAAAA;
+
+// the watchdog runs from the 32 kHz LSI, divided by 4 << PR
+const LSI_FREQUENCY: u32 = 32_000;
+const PRESCALER: u32 = 4;
+const MAX_RELOAD: u32 = 0xFFF;
+
+// stops the watchdog while the core is halted by the debugger
+const DBGMCU_APB1_FZ: usize = 0xE004_2008;
+const DBG_IWDG_STOP: u32 = 1 << 12;
+
+const RCC_CSR: usize = 0x4002_3874;
+const CSR_RMVF: u32 = 1 << 24;
+
+static RESET_FLAGS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResetCause {
+    LowPower,
+    WindowWatchdog,
+    Watchdog,
+    Software,
+    PowerOn,
+    Pin,
+    BrownOut,
+    Unknown,
+}
+
+impl ResetCause {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            ResetCause::LowPower => "low power management",
+            ResetCause::WindowWatchdog => "window watchdog",
+            ResetCause::Watchdog => "watchdog, the main loop stalled",
+            ResetCause::Software => "software reset",
+            ResetCause::PowerOn => "power on",
+            ResetCause::Pin => "reset button",
+            ResetCause::BrownOut => "brown out",
+            ResetCause::Unknown => "unknown",
+        }
+    }
+
+    pub fn is_watchdog(&self) -> bool {
+        *self == ResetCause::Watchdog || *self == ResetCause::WindowWatchdog
+    }
+}
+
+/// Saves and clears the reset flags of the RCC, has to be called once after
+/// the .bss section was initialized.
+pub fn read_reset_flags() {
+    unsafe {
+        let csr = ptr::read_volatile(RCC_CSR as *const u32);
+        RESET_FLAGS.store(csr as usize, Ordering::SeqCst);
+        ptr::write_volatile(RCC_CSR as *mut u32, csr | CSR_RMVF);
+    }
+}
+
+/// The reason of the last reset. Several flags are set at once, e.g. the pin
+/// flag is set on every reset, so the most specific one is returned.
+pub fn reset_cause() -> ResetCause {
+    let flags = RESET_FLAGS.load(Ordering::SeqCst) as u32;
+    let causes = [
+        (31, ResetCause::LowPower),
+        (30, ResetCause::WindowWatchdog),
+        (29, ResetCause::Watchdog),
+        (28, ResetCause::Software),
+        (27, ResetCause::PowerOn),
+        (25, ResetCause::BrownOut),
+        (26, ResetCause::Pin),
+    ];
+    for &(bit, cause) in causes.iter() {
+        if flags & (1 << bit) != 0 {
+            return cause;
+        }
+    }
+    ResetCause::Unknown
+}
+
+/// Starts the independent watchdog, which resets the board if `feed` is not
+/// called for `timeout_ms` milliseconds. It can't be stopped anymore.
+pub fn init(timeout_ms: u32) {
+    let ticks_per_second = LSI_FREQUENCY / (4 << PRESCALER);
+    let mut reload = timeout_ms * ticks_per_second / 1000;
+    if reload > MAX_RELOAD {
+        reload = MAX_RELOAD;
+    }
+    unsafe {
+        let freeze = ptr::read_volatile(DBGMCU_APB1_FZ as *const u32);
+        ptr::write_volatile(DBGMCU_APB1_FZ as *mut u32, freeze | DBG_IWDG_STOP);
+
+        ptr::write_volatile(IWDG_KR as *mut u32, KEY_START);
+        ptr::write_volatile(IWDG_KR as *mut u32, KEY_ACCESS);
+        ptr::write_volatile(IWDG_PR as *mut u32, PRESCALER);
+        ptr::write_volatile(IWDG_RLR as *mut u32, reload);
+        // wait until the values reached the watchdog clock domain
+        while ptr::read_volatile(IWDG_SR as *const u32) != 0 {}
+    }
+    feed();
+}
+
+pub fn feed() {
+    unsafe {
+        ptr::write_volatile(IWDG_KR as *mut u32, KEY_FEED);
+    }
+}
@@ -0,0 +1,2 @@
+for /f "tokens=2" %%i in ('rustc -vV ^| findstr host:') do set HOST=%%i
+cargo test --manifest-path host_tests/Cargo.toml --target %HOST%
@@ -0,0 +1,6 @@
+#!/bin/bash
+
+set -e
+
+# runs the tests of the hardware independent modules on the host, see host_tests/src/lib.rs
+cargo test --manifest-path host_tests/Cargo.toml --target "$(rustc -vV | sed -n 's/^host: //p')" "$@"